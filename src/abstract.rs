@@ -1,5 +1,9 @@
-use std::process::Command;
-use std::time::Instant;
+use crate::{error::CleanError, parser::join_args};
+use std::io::{self, Read};
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub struct Timer {
     start: Instant,
@@ -17,17 +21,102 @@ impl Timer {
     }
 }
 
-pub fn run_command(exe: &str, args: &str) -> Result<(), u32> {
-    let output = Command::new(exe).args(args.split_whitespace()).output();
+/// Runs `exe` with `args`, capturing its output.
+pub fn run_command(exe: &str, args: &[String]) -> Result<(), CleanError> {
+    run(exe, args).map(|_| ())
+}
+
+/// Runs `exe` like `run_command` and returns the first line it printed, e.g. the cache
+/// directory from `go env GOCACHE`.
+pub fn query_command(exe: &str, args: &[String]) -> Result<String, CleanError> {
+    let output = run(exe, args)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    match stdout.lines().map(str::trim).find(|l| !l.is_empty()) {
+        Some(line) => Ok(line.to_string()),
+        None => Err(CleanError::Expand(format!(
+            "`{} {}` printed nothing",
+            exe,
+            join_args(args)
+        ))),
+    }
+}
+
+fn run(exe: &str, args: &[String]) -> Result<Output, CleanError> {
+    let output = Command::new(exe)
+        .args(args)
+        .output()
+        .map_err(|err| CleanError::from_io(exe, &err))?;
+
+    if output.status.success() {
+        return Ok(output);
+    }
+
+    // The last line is usually the one that says what went wrong.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let last_line = stderr.lines().rev().find(|l| !l.trim().is_empty());
 
-    match output {
-        Ok(result) => {
-            if result.status.success() {
-                Ok(())
-            } else {
-                Err(result.status.code().unwrap_or(1) as u32)
+    Err(CleanError::Exit {
+        code: output.status.code().unwrap_or(1),
+        stderr: last_line.unwrap_or("").trim().to_string(),
+    })
+}
+
+/// How much of a probe's output is kept for matching.
+const PROBE_OUTPUT_LIMIT: u64 = 64 * 1024;
+
+/// How long the output may still take once the probe exited, even right at its timeout.
+const PROBE_OUTPUT_GRACE: Duration = Duration::from_millis(500);
+
+/// Runs `exe` for detection, killing it once `timeout` passes. Returns its exit code and
+/// standard output, up to `PROBE_OUTPUT_LIMIT`, or `None` when it could not start or took
+/// too long.
+pub fn probe_command(exe: &str, args: &[String], timeout: Duration) -> Option<(i32, String)> {
+    let start = Instant::now();
+    let mut child = Command::new(exe)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    // Read on the side so a chatty probe can't fill the pipe and stall.
+    let mut stdout = child.stdout.take()?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = (&mut stdout)
+            .take(PROBE_OUTPUT_LIMIT)
+            .read_to_end(&mut output);
+        let _ = sender.send(output);
+        // Keep draining so the probe doesn't block on a full pipe.
+        let _ = io::copy(&mut stdout, &mut io::sink());
+    });
+
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if start.elapsed() < timeout => thread::sleep(Duration::from_millis(10)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
             }
         }
-        Err(_) => Err(1),
-    }
+    };
+
+    // Something it started in the background may still hold the pipe open.
+    let output = receiver
+        .recv_timeout(
+            timeout
+                .saturating_sub(start.elapsed())
+                .max(PROBE_OUTPUT_GRACE),
+        )
+        .ok()?;
+
+    Some((
+        status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output).into_owned(),
+    ))
 }
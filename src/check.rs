@@ -0,0 +1,162 @@
+use crate::{
+    files::{expand_home, expand_vars},
+    minimessage_const::ConstStr,
+    parser::{parse_rules, ParseError, ParsedRule},
+    report::{Record, Report},
+    rules::{read_source, CleanMethod, SizeDir},
+    ui::push_escaped,
+};
+use std::path::Path;
+use std::process::exit;
+
+/// Problems that parse fine but are almost certainly mistakes.
+fn lint(file: &str, rules: &[ParsedRule]) -> Vec<ParseError> {
+    let mut problems = Vec::new();
+    let mut problem = |line: usize, message: String| {
+        problems.push(ParseError {
+            file: file.to_string(),
+            line,
+            column: 1,
+            message,
+        })
+    };
+
+    for (i, parsed) in rules.iter().enumerate() {
+        let rule = &parsed.rule;
+
+        // Selection is case-insensitive, so are duplicates.
+        if let Some(first) = rules[..i]
+            .iter()
+            .find(|r| r.rule.name.eq_ignore_ascii_case(rule.name.as_str()))
+        {
+            problem(
+                parsed.line,
+                format!(
+                    "rule `{}` is already defined on line {}",
+                    rule.name.as_str(),
+                    first.line
+                ),
+            );
+        }
+
+        // A queried size dir is only known when the tool runs.
+        let Some(SizeDir::Path(size_dir)) = &rule.size_dir else {
+            continue;
+        };
+
+        // Compared by their defaults, or by name when they have none, whatever the
+        // variables happen to be set to here.
+        let canonical = |path: &str| {
+            expand_vars(path, &|_| None)
+                .or_else(|_| expand_vars(path, &|name| Some(format!("${{{}}}", name))))
+                .ok()
+                .map(|path| expand_home(&path))
+        };
+        let Some(size_dir) = canonical(size_dir) else {
+            continue;
+        };
+
+        for step in &rule.clean {
+            let clean_path = match &step.method {
+                CleanMethod::CleanPath(path)
+                | CleanMethod::CleanOlderThan(path, _)
+                | CleanMethod::CleanToBudget(path, _) => path,
+                CleanMethod::RunCommand(..) => continue,
+            };
+            let Some(clean_path) = canonical(clean_path) else {
+                continue;
+            };
+
+            if !Path::new(clean_path.as_str()).starts_with(size_dir.as_str()) {
+                problem(
+                    parsed.line,
+                    format!(
+                        "rule `{}` cleans `{}` which is outside its size dir `{}`",
+                        rule.name.as_str(),
+                        clean_path.as_str(),
+                        size_dir.as_str()
+                    ),
+                );
+            }
+        }
+    }
+
+    problems
+}
+
+/// Checks one file, printing its problems. Returns whether it is clean.
+fn check_file(source: &str, report: &mut Report) -> bool {
+    let mut msg = ConstStr::<512>::new();
+
+    let content = match read_source(source) {
+        Ok(content) => content,
+        Err(err) => {
+            msg.push_str("<red>Error: <white>Could not read <gray>");
+            push_escaped(&mut msg, source);
+            msg.push_str("<white>: ");
+            push_escaped(&mut msg, &err.to_string());
+            report.text(msg);
+
+            report.record(
+                Record::new("problem")
+                    .str("file", source)
+                    .str("message", &err.to_string()),
+            );
+            return false;
+        }
+    };
+
+    let file = parse_rules(source, &content);
+    let mut problems = file.errors;
+    problems.extend(lint(source, &file.rules));
+    problems.sort_by_key(|p| (p.line, p.column));
+
+    for problem in &problems {
+        msg.clear();
+        msg.push_str("<red>Error: <gray>");
+        push_escaped(&mut msg, &problem.to_string());
+        report.text(msg);
+
+        report.record(
+            Record::new("problem")
+                .str("file", &problem.file)
+                .u64("line", problem.line as u64)
+                .u64("column", problem.column as u64)
+                .str("message", &problem.message),
+        );
+    }
+
+    msg.clear();
+    if problems.is_empty() {
+        msg.push_str("<green>OK: <white>");
+        msg.push_u64(file.rules.len() as u64);
+        msg.push_str(" rules in <gray>");
+    } else {
+        msg.push_str("<red>");
+        msg.push_u64(problems.len() as u64);
+        msg.push_str(" problems in <gray>");
+    }
+    push_escaped(&mut msg, source);
+    report.text(msg);
+
+    problems.is_empty()
+}
+
+/// `nil check [PATH]...`, validates rules files and exits with 1 if anything is wrong.
+pub fn run(files: &[String], report: &mut Report) {
+    if files.is_empty() {
+        report.text("<red>Error: <gray>No rules files found.");
+        report.finish();
+        exit(1);
+    }
+
+    let mut ok = true;
+    for file in files {
+        ok &= check_file(file, report);
+    }
+    report.finish();
+
+    if !ok {
+        exit(1);
+    }
+}
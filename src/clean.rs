@@ -0,0 +1,918 @@
+use crate::{
+    cli::Options,
+    config::jobs,
+    error::CleanError,
+    files::{
+        clear_dir, expand_glob, expand_path, folder_size, glob_size, human_duration, human_size,
+        prune_older_than, trim_to_budget, walk_dir,
+    },
+    minimessage_const::ConstStr,
+    parser::join_args,
+    r#abstract::{run_command, Timer},
+    report::{Record, Report},
+    rules::{is_rule_active, CacheRule, CleanMethod, CleanStep, OnError, SizeDir},
+    trash::move_to_trash,
+    ui::{ask, eprint_styled, print_styled, push_escaped},
+};
+use std::io::IsTerminal;
+use std::process::exit;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Errors printed per rule before the rest are summarized as a count.
+const MAX_SHOWN_ERRORS: usize = 5;
+
+/// A rule that was confirmed for cleaning.
+struct Plan<'a> {
+    rule: &'a CacheRule,
+    steps: Vec<CleanStep>,
+    size_dir: Option<String>,
+    size: u64,
+}
+
+/// What happened to a single rule during `clean`.
+struct Outcome {
+    name: String,
+    errors: usize,
+    /// Bytes the size dir shrank by, unknown for rules without one.
+    freed: Option<u64>,
+    /// Bytes moved to the trash, still on disk so not part of `freed`.
+    trashed: u64,
+}
+
+/// Where the styled lines of a rule go. Printed right away when rules are cleaned one at
+/// a time, held back so parallel rules don't interleave their output.
+struct Output {
+    text: bool,
+    buffer: Option<Vec<String>>,
+}
+
+impl Output {
+    fn push(&mut self, line: impl AsRef<str>) {
+        if !self.text {
+            return;
+        }
+
+        match &mut self.buffer {
+            Some(buffer) => buffer.push(line.as_ref().to_string()),
+            None => print_styled(line),
+        }
+    }
+}
+
+/// What cleaning a rule came to, its lines are in its `Output`.
+struct Cleaned {
+    record: Record,
+    /// One record per step, only for rules with more than one.
+    steps: Vec<Record>,
+    outcome: Outcome,
+    /// Bytes this rule adds to the final total.
+    counted: u64,
+}
+
+pub fn run(rules: &[CacheRule], options: &Options, report: &mut Report) {
+    let start = Timer::now();
+    let is_dry_run = options.is_dry_run;
+
+    let mut msg = ConstStr::<256>::new();
+
+    // Without a terminal there is nobody to answer the prompt, so refuse outright.
+    let mut confirm = !options.assume_yes && !is_dry_run;
+    if confirm && !std::io::stdin().is_terminal() {
+        eprint_styled(
+            "<red>Error: <white>Refusing to clean without confirmation, stdin is not a terminal. Pass <yellow>--yes</yellow> to skip the prompt.",
+        );
+        exit(1);
+    }
+
+    // Everything is confirmed up front so rules can then be cleaned in parallel.
+    let mut plans: Vec<Plan> = Vec::new();
+    let mut outcomes: Vec<Outcome> = Vec::new();
+
+    for tool in rules {
+        if !options.selects(&tool.name) || !is_rule_active(tool) {
+            continue;
+        }
+
+        let is_unsafe = options.is_unsafe && tool.size_dir.is_some();
+
+        // Commands can't be limited by age, and a budget would turn into a different rule.
+        let unlimited = tool.clean.iter().find_map(|step| match step.method {
+            CleanMethod::RunCommand(..) => Some(", its command can't be limited by age."),
+            CleanMethod::CleanToBudget(..) => {
+                Some(", its size budget can't be combined with an age.")
+            }
+            _ => None,
+        });
+        if let Some(reason) = unlimited.filter(|_| options.older_than.is_some() && !is_unsafe) {
+            msg.clear();
+            msg.push_str("<gray>Skipped ");
+            push_escaped(&mut msg, &tool.name);
+            msg.push_str(reason);
+            msg.push_str(" Add <yellow>--unsafe</yellow> to prune its cache directory instead.");
+            report.text(msg);
+            continue;
+        }
+
+        // Pruned files are removed for good, only whole directories go to the trash.
+        let prunes = tool.clean.iter().any(|step| {
+            matches!(
+                step.method,
+                CleanMethod::CleanOlderThan(..) | CleanMethod::CleanToBudget(..)
+            )
+        });
+        if options.trash && prunes && !is_unsafe {
+            msg.clear();
+            msg.push_str("<gray>Skipped ");
+            push_escaped(&mut msg, &tool.name);
+            msg.push_str(", the files it prunes can't be moved to the trash. Add <yellow>--unsafe</yellow> to trash its cache directory instead.");
+            report.text(msg);
+            continue;
+        }
+
+        // Resolved once here, so what was confirmed is exactly what gets cleaned.
+        let resolved = tool
+            .size_dir
+            .as_ref()
+            .map(SizeDir::resolve)
+            .transpose()
+            .and_then(|size_dir| {
+                let steps = match &size_dir {
+                    Some(dir) if is_unsafe => {
+                        vec![CleanStep::new(CleanMethod::CleanPath(dir.clone()))]
+                    }
+                    _ => tool
+                        .clean
+                        .iter()
+                        .map(|step| {
+                            Ok(CleanStep {
+                                method: resolve(step.method.clone())?,
+                                on_error: step.on_error,
+                            })
+                        })
+                        .collect::<Result<_, CleanError>>()?,
+                };
+                Ok((steps, size_dir))
+            });
+        let (mut steps, size_dir) = match resolved {
+            Ok(resolved) => resolved,
+            Err(err) => {
+                msg.clear();
+                msg.push_str("<red>Failed: <white>");
+                push_escaped(&mut msg, &tool.name);
+                msg.push_str(": ");
+                push_escaped(&mut msg, &err.to_string());
+                report.text(msg);
+
+                report.record(
+                    Record::new("clean")
+                        .str("name", &tool.name)
+                        .bool("dry_run", is_dry_run)
+                        .str("error", &err.to_string())
+                        .str("error_kind", err.kind())
+                        .u64("errors", 1)
+                        .bool("ok", false),
+                );
+                outcomes.push(Outcome {
+                    name: tool.name.clone(),
+                    errors: 1,
+                    freed: None,
+                    trashed: 0,
+                });
+                continue;
+            }
+        };
+
+        if let Some(age) = options.older_than {
+            for step in &mut steps {
+                if let CleanMethod::CleanPath(path) | CleanMethod::CleanOlderThan(path, _) =
+                    &step.method
+                {
+                    step.method = CleanMethod::CleanOlderThan(path.clone(), age.as_secs());
+                }
+            }
+        }
+        let size = size_dir.as_ref().map_or(0, |dir| glob_size(dir));
+
+        if confirm {
+            msg.clear();
+            msg.push_str("<yellow>Clean ");
+            push_escaped(&mut msg, &tool.name);
+            msg.push_str(" <gray>(");
+            for (i, step) in steps.iter().enumerate() {
+                if i > 0 {
+                    msg.push_str(", then ");
+                }
+                match &step.method {
+                    CleanMethod::RunCommand(cmd, args) => {
+                        msg.push_str("run: ");
+                        push_escaped(&mut msg, &join_args(slice::from_ref(cmd)));
+                        msg.push_u8(b' ');
+                        push_escaped(&mut msg, &join_args(args));
+                    }
+                    CleanMethod::CleanPath(path) => {
+                        msg.push_str(if options.trash { "trash: " } else { "clear: " });
+                        push_escaped(&mut msg, path);
+                    }
+                    CleanMethod::CleanOlderThan(path, age) => {
+                        msg.push_str("prune older than ");
+                        msg.push_str(&human_duration(*age));
+                        msg.push_str(": ");
+                        push_escaped(&mut msg, path);
+                    }
+                    CleanMethod::CleanToBudget(path, budget) => {
+                        msg.push_str("trim to ");
+                        msg.push_str(&human_size(*budget));
+                        msg.push_str(": ");
+                        push_escaped(&mut msg, path);
+                    }
+                }
+            }
+            msg.push_str(", ");
+            msg.push_str(&human_size(size));
+            msg.push_str(")<white>? [y/N/a/q] ");
+
+            match ask(msg) {
+                Some('y') => {}
+                Some('a') => confirm = false,
+                Some('q') => break,
+                _ => {
+                    msg.clear();
+                    msg.push_str("<gray>Skipped ");
+                    push_escaped(&mut msg, &tool.name);
+                    msg.push_u8(b'.');
+                    report.text(msg);
+                    continue;
+                }
+            }
+        }
+
+        plans.push(Plan {
+            rule: tool,
+            steps,
+            size_dir,
+            size,
+        });
+    }
+
+    let count = (plans.len() + outcomes.len()) as u64;
+    let workers = if options.parallel {
+        jobs().min(plans.len()).max(1)
+    } else {
+        1
+    };
+
+    if workers > 1 {
+        msg.clear();
+        msg.push_str("<gray>Cleaning ");
+        msg.push_u64(count);
+        msg.push_str(" caches on ");
+        msg.push_u64(workers as u64);
+        msg.push_str(" threads.");
+        report.text(msg);
+    }
+
+    let mut total: u64 = 0;
+
+    let text = report.is_text();
+    let trash = options.trash;
+    let mut finish = |cleaned: Cleaned| {
+        report.record(cleaned.record);
+        for step in cleaned.steps {
+            report.record(step);
+        }
+
+        total += cleaned.counted;
+        outcomes.push(cleaned.outcome);
+    };
+
+    if workers == 1 {
+        for plan in &plans {
+            let mut out = Output { text, buffer: None };
+            finish(clean_rule(plan, is_dry_run, trash, &mut out));
+        }
+    } else {
+        // Workers take the next plan in order and hand back its output once it is done.
+        let next = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel::<(Vec<String>, Cleaned)>();
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                let (plans, next, sender) = (&plans, &next, sender.clone());
+                scope.spawn(move || {
+                    while let Some(plan) = plans.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let mut out = Output {
+                            text,
+                            buffer: Some(Vec::new()),
+                        };
+                        let cleaned = clean_rule(plan, is_dry_run, trash, &mut out);
+                        let _ = sender.send((out.buffer.unwrap_or_default(), cleaned));
+                    }
+                });
+            }
+            drop(sender);
+
+            for (lines, cleaned) in receiver {
+                for line in &lines {
+                    print_styled(line);
+                }
+                finish(cleaned);
+            }
+        });
+    }
+
+    let failed = outcomes.iter().filter(|o| o.errors > 0).count() as u64;
+    let trashed: u64 = outcomes.iter().map(|o| o.trashed).sum();
+
+    if !is_dry_run && !outcomes.is_empty() {
+        report.text("\n<white>Summary:");
+
+        for outcome in &outcomes {
+            msg.clear();
+            msg.push_str("  ");
+            if outcome.errors == 0 {
+                msg.push_str("<green>ok     <white>");
+            } else {
+                msg.push_str("<red>failed <white>");
+            }
+            push_escaped(&mut msg, &outcome.name);
+
+            msg.push_str(" <gray>(");
+            match outcome.freed {
+                Some(0) => msg.push_str("nothing freed"),
+                Some(freed) => {
+                    msg.push_str(&human_size(freed));
+                    msg.push_str(" freed");
+                }
+                None => msg.push_str("size unknown"),
+            }
+            if outcome.trashed > 0 {
+                msg.push_str(", ");
+                msg.push_str(&human_size(outcome.trashed));
+                msg.push_str(" moved to trash");
+            }
+            if outcome.errors > 0 {
+                msg.push_str(", ");
+                msg.push_u64(outcome.errors as u64);
+                msg.push_str(if outcome.errors == 1 {
+                    " error"
+                } else {
+                    " errors"
+                });
+            }
+            msg.push_u8(b')');
+            report.text(msg);
+        }
+        report.text("");
+    }
+
+    msg.clear();
+    if is_dry_run {
+        msg.push_str("<green>Dry run: would clean ");
+        msg.push_u64(count);
+        msg.push_str(" caches <dark_green>(");
+        msg.push_str(&human_size(total));
+        msg.push_str(")</dark_green>. Nothing was changed.");
+    } else if failed > 0 {
+        msg.push_str("<red>Finished with errors. ");
+        msg.push_u64(failed);
+        msg.push_str(" of ");
+        msg.push_u64(count);
+        msg.push_str(" caches failed to clean, freed ");
+        msg.push_str(&human_size(total));
+        if trashed > 0 {
+            msg.push_str(" and moved ");
+            msg.push_str(&human_size(trashed));
+            msg.push_str(" to trash");
+        }
+        msg.push_str(". Took ");
+        msg.push_u64(start.elapsed_ms());
+        msg.push_str(" ms.");
+    } else {
+        msg.push_str("<green>Done! Cleaned ");
+        msg.push_u64(count);
+        msg.push_str(" caches <dark_green>(");
+        msg.push_str(&human_size(total));
+        msg.push_str(" freed");
+        if trashed > 0 {
+            msg.push_str(", ");
+            msg.push_str(&human_size(trashed));
+            msg.push_str(" moved to trash");
+        }
+        msg.push_str(")</dark_green>. Took ");
+        msg.push_u64(start.elapsed_ms());
+        msg.push_str(" ms.");
+    }
+    report.text(msg);
+
+    let mut record = Record::new("total")
+        .bool("dry_run", is_dry_run)
+        .u64("cleaned", count - failed)
+        .u64("failed", failed)
+        .u64(
+            if is_dry_run {
+                "size_bytes"
+            } else {
+                "freed_bytes"
+            },
+            total,
+        );
+    if options.trash && !is_dry_run {
+        record = record.u64("trashed_bytes", trashed);
+    }
+    report.record(record.u64("elapsed_ms", start.elapsed_ms()));
+    report.finish();
+
+    if failed > 0 {
+        exit(1);
+    }
+}
+
+/// Expands the path of the path based methods, commands are left as they are.
+fn resolve(clean: CleanMethod) -> Result<CleanMethod, CleanError> {
+    Ok(match clean {
+        CleanMethod::CleanPath(path) => CleanMethod::CleanPath(expand_path(&path)?),
+        CleanMethod::CleanOlderThan(path, age) => {
+            CleanMethod::CleanOlderThan(expand_path(&path)?, age)
+        }
+        CleanMethod::CleanToBudget(path, budget) => {
+            CleanMethod::CleanToBudget(expand_path(&path)?, budget)
+        }
+        command => command,
+    })
+}
+
+/// Errors gathered over every match of a path.
+fn collect_errors(errors: Vec<CleanError>) -> Result<(), Vec<CleanError>> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// What a single step of a rule did.
+struct Step {
+    record: Record,
+    result: Result<(), Vec<CleanError>>,
+    /// Bytes a dry run would remove, `None` when it is the whole cache.
+    removes: Option<u64>,
+    /// Bytes moved to the trash.
+    trashed: u64,
+}
+
+/// Runs one step of a rule, adding what it did to `record`.
+fn clean_step(
+    method: &CleanMethod,
+    plan: &Plan,
+    is_dry_run: bool,
+    trash: bool,
+    out: &mut Output,
+    mut record: Record,
+) -> Step {
+    let tool = plan.rule;
+    let size = plan.size;
+    let mut msg = ConstStr::<256>::new();
+    let mut removes = None;
+    let mut trashed = 0;
+
+    let result = match method {
+        CleanMethod::RunCommand(cmd, args) => {
+            msg.clear();
+            if is_dry_run {
+                msg.push_str("<#D4D4D4>Would run command: <gray>");
+            } else {
+                msg.push_str("<#D4D4D4>Running command: <gray>");
+            }
+            let args_line = join_args(args);
+            push_escaped(&mut msg, &join_args(slice::from_ref(cmd)));
+            msg.push_u8(b' ');
+            push_escaped(&mut msg, &args_line);
+            out.push(msg);
+
+            record = record
+                .str("method", "command")
+                .str("command", cmd)
+                .str("args", &args_line);
+
+            if is_dry_run {
+                msg.clear();
+                msg.push_str("<#D4D4D4>Cache size: <gray>");
+                msg.push_str(&human_size(size));
+                out.push(msg);
+
+                record = record.opt_i64("exit_code", None);
+                Ok(())
+            } else {
+                let result = run_command(cmd, args);
+                let code = match &result {
+                    Ok(()) => Some(0),
+                    Err(CleanError::Exit { code, .. }) => Some(*code as i64),
+                    Err(_) => None,
+                };
+
+                record = record.opt_i64("exit_code", code);
+                result.map_err(|err| vec![err])
+            }
+        }
+        CleanMethod::CleanPath(pattern) => {
+            let matches = expand_glob(pattern);
+            let mut errors = Vec::new();
+            let mut trash_dirs: Vec<String> = Vec::new();
+            let mut line = ConstStr::<512>::new();
+            let mut entries: u64 = 0;
+            let mut bytes: u64 = 0;
+
+            record = record
+                .str("method", "path")
+                .str("path", pattern)
+                .u64("matches", matches.len() as u64)
+                .bool("trash", trash);
+
+            for dir in &matches {
+                msg.clear();
+                msg.push_str(match (is_dry_run, trash) {
+                    (true, false) => "<#D4D4D4>Would clear directory: <gray>\"",
+                    (false, false) => "<#D4D4D4>Clearing directory: <gray>\"",
+                    (true, true) => "<#D4D4D4>Would move to trash: <gray>\"",
+                    (false, true) => "<#D4D4D4>Moving to trash: <gray>\"",
+                });
+                push_escaped(&mut msg, dir);
+                msg.push_str("\"");
+                out.push(msg);
+
+                if is_dry_run {
+                    walk_dir(dir, &mut |entry, metadata| {
+                        entries += 1;
+                        if !metadata.is_dir() {
+                            bytes += metadata.len();
+                        }
+
+                        if out.text {
+                            line.clear();
+                            line.push_str("    <gray>- ");
+                            push_escaped(&mut line, entry.to_str().unwrap_or("?"));
+                            if metadata.is_dir() {
+                                line.push_u8(b'/');
+                            }
+                            out.push(line);
+                        }
+                    });
+                } else if trash {
+                    match move_to_trash(&tool.name, dir) {
+                        Ok(None) => out.push("<gray>Nothing to move to the trash."),
+                        Ok(Some(batch)) => {
+                            let trash_dir = batch.dir.to_str().unwrap_or("?");
+
+                            msg.clear();
+                            msg.push_str("<#D4D4D4>Moved to: <gray>\"");
+                            push_escaped(&mut msg, trash_dir);
+                            msg.push_str("\" <#555555>(undo with nil restore ");
+                            push_escaped(&mut msg, &tool.name);
+                            msg.push_u8(b')');
+                            out.push(msg);
+
+                            trashed += folder_size(batch.files().to_str().unwrap_or(""));
+                            trash_dirs.push(trash_dir.to_string());
+                        }
+                        Err(err) => errors.extend(err),
+                    }
+                } else if let Err(err) = clear_dir(dir) {
+                    errors.extend(err);
+                }
+            }
+
+            if is_dry_run {
+                msg.clear();
+                msg.push_str("<#D4D4D4>Would remove <white>");
+                msg.push_u64(entries);
+                msg.push_str("</white> entries <gray>(");
+                msg.push_str(&human_size(bytes));
+                msg.push_str(")");
+                out.push(msg);
+
+                record = record.u64("entries", entries);
+            }
+            // A glob makes one batch per match, `nil restore` brings them all back.
+            if let [trash_dir] = trash_dirs.as_slice() {
+                record = record.str("trash_dir", trash_dir);
+            }
+
+            collect_errors(errors)
+        }
+        CleanMethod::CleanOlderThan(pattern, age) => {
+            let matches = expand_glob(pattern);
+            let mut errors = Vec::new();
+
+            record = record
+                .str("method", "older_than")
+                .str("path", pattern)
+                .u64("matches", matches.len() as u64)
+                .u64("max_age_secs", *age);
+
+            let cutoff = SystemTime::now()
+                .checked_sub(Duration::from_secs(*age))
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+
+            let mut line = ConstStr::<512>::new();
+            let mut entries: u64 = 0;
+            let mut bytes: u64 = 0;
+
+            for dir in &matches {
+                msg.clear();
+                if is_dry_run {
+                    msg.push_str("<#D4D4D4>Would prune entries older than ");
+                } else {
+                    msg.push_str("<#D4D4D4>Pruning entries older than ");
+                }
+                msg.push_str(&human_duration(*age));
+                msg.push_str(" in: <gray>\"");
+                push_escaped(&mut msg, dir);
+                msg.push_str("\"");
+                out.push(msg);
+
+                let result = prune_older_than(dir, cutoff, is_dry_run, &mut |entry, metadata| {
+                    entries += 1;
+                    if !metadata.is_dir() {
+                        bytes += metadata.len();
+                    }
+
+                    if is_dry_run && out.text {
+                        line.clear();
+                        line.push_str("    <gray>- ");
+                        push_escaped(&mut line, entry.to_str().unwrap_or("?"));
+                        if metadata.is_dir() {
+                            line.push_u8(b'/');
+                        }
+                        out.push(line);
+                    }
+                });
+                if let Err(err) = result {
+                    errors.extend(err);
+                }
+            }
+
+            msg.clear();
+            msg.push_str(if is_dry_run {
+                "<#D4D4D4>Would remove <white>"
+            } else {
+                "<#D4D4D4>Removed <white>"
+            });
+            msg.push_u64(entries);
+            msg.push_str("</white> entries <gray>(");
+            msg.push_str(&human_size(bytes));
+            msg.push_str(")");
+            out.push(msg);
+
+            record = record.u64("entries", entries);
+            removes = Some(bytes);
+            collect_errors(errors)
+        }
+        CleanMethod::CleanToBudget(pattern, budget) => {
+            let matches = expand_glob(pattern);
+            let mut errors = Vec::new();
+
+            record = record
+                .str("method", "budget")
+                .str("path", pattern)
+                .u64("matches", matches.len() as u64)
+                .u64("budget_bytes", *budget);
+
+            let mut line = ConstStr::<512>::new();
+            let mut evicted: u64 = 0;
+            let mut bytes: u64 = 0;
+            let mut remaining: u64 = 0;
+
+            // Every match is trimmed to the budget on its own.
+            for dir in &matches {
+                msg.clear();
+                if is_dry_run {
+                    msg.push_str("<#D4D4D4>Would trim to ");
+                } else {
+                    msg.push_str("<#D4D4D4>Trimming to ");
+                }
+                msg.push_str(&human_size(*budget));
+                msg.push_str(": <gray>\"");
+                push_escaped(&mut msg, dir);
+                msg.push_str("\"");
+                out.push(msg);
+
+                let result = trim_to_budget(dir, *budget, is_dry_run, &mut |entry, metadata| {
+                    evicted += 1;
+                    bytes += metadata.len();
+
+                    if out.text {
+                        line.clear();
+                        line.push_str("    <gray>- ");
+                        push_escaped(&mut line, entry.to_str().unwrap_or("?"));
+                        line.push_str(" <#555555>(");
+                        line.push_str(&human_size(metadata.len()));
+                        line.push_u8(b')');
+                        out.push(line);
+                    }
+                });
+                match result {
+                    Ok(left) => remaining += left,
+                    Err(err) => errors.extend(err),
+                }
+            }
+
+            msg.clear();
+            msg.push_str(if is_dry_run {
+                "<#D4D4D4>Would evict <white>"
+            } else {
+                "<#D4D4D4>Evicted <white>"
+            });
+            msg.push_u64(evicted);
+            msg.push_str("</white> least recently used files <gray>(");
+            msg.push_str(&human_size(bytes));
+            msg.push_str(")");
+            if errors.is_empty() {
+                msg.push_str(", <#D4D4D4>leaving <gray>");
+                msg.push_str(&human_size(remaining));
+            }
+            out.push(msg);
+
+            record = record.u64("entries", evicted).u64("evicted_bytes", bytes);
+            removes = Some(bytes);
+            collect_errors(errors)
+        }
+    };
+
+    Step {
+        record,
+        result,
+        removes,
+        trashed,
+    }
+}
+
+/// Cleans one rule, its styled lines go to `out`.
+fn clean_rule(plan: &Plan, is_dry_run: bool, trash: bool, out: &mut Output) -> Cleaned {
+    let tool = plan.rule;
+    let size = plan.size;
+    let rule_start = Timer::now();
+
+    let mut msg = ConstStr::<256>::new();
+
+    msg.clear();
+    if is_dry_run {
+        msg.push_str("<yellow>Would clean cache of ");
+    } else {
+        msg.push_str("<yellow>Cleaning cache of ");
+    }
+    push_escaped(&mut msg, &tool.name);
+    msg.push_str("...");
+    out.push(msg);
+
+    let rule_record = || {
+        Record::new("clean")
+            .str("name", &tool.name)
+            .bool("dry_run", is_dry_run)
+            .u64("size_bytes", size)
+    };
+
+    let count = plan.steps.len();
+    let mut record = None;
+    let mut steps = Vec::new();
+    let mut errors: Vec<CleanError> = Vec::new();
+    // What a dry run would remove, `None` once a step takes the whole cache.
+    let mut removes = Some(0);
+    let mut trashed = 0;
+
+    for (i, step) in plan.steps.iter().enumerate() {
+        let step_record = if count > 1 {
+            msg.clear();
+            msg.push_str("<#D4D4D4>Step ");
+            msg.push_u64(i as u64 + 1);
+            msg.push_u8(b'/');
+            msg.push_u64(count as u64);
+            msg.push_u8(b':');
+            out.push(msg);
+
+            Record::new("step")
+                .str("name", &tool.name)
+                .u64("step", i as u64 + 1)
+        } else {
+            rule_record()
+        };
+
+        let Step {
+            record: mut step_record,
+            result,
+            removes: step_removes,
+            trashed: step_trashed,
+        } = clean_step(&step.method, plan, is_dry_run, trash, out, step_record);
+        removes = removes
+            .zip(step_removes)
+            .map(|(total, bytes)| total + bytes);
+        trashed += step_trashed;
+
+        if let Err(step_errors) = result {
+            for err in step_errors.iter().take(MAX_SHOWN_ERRORS) {
+                msg.clear();
+                msg.push_str("<red>Failed: <white>");
+                push_escaped(&mut msg, &err.to_string());
+                out.push(msg);
+            }
+
+            if step_errors.len() > MAX_SHOWN_ERRORS {
+                msg.clear();
+                msg.push_str("<red>...and ");
+                msg.push_u64((step_errors.len() - MAX_SHOWN_ERRORS) as u64);
+                msg.push_str(" more.");
+                out.push(msg);
+            }
+
+            if count > 1 {
+                step_record = step_record
+                    .str("error", &step_errors[0].to_string())
+                    .str("error_kind", step_errors[0].kind())
+                    .u64("errors", step_errors.len() as u64)
+                    .bool("ok", false);
+            }
+            errors.extend(step_errors);
+
+            if step.on_error == OnError::Stop && i + 1 < count {
+                steps.push(step_record);
+
+                msg.clear();
+                msg.push_str("<gray>Skipped the remaining ");
+                msg.push_u64((count - i - 1) as u64);
+                msg.push_str(if count - i - 1 == 1 {
+                    " step."
+                } else {
+                    " steps."
+                });
+                out.push(msg);
+                break;
+            }
+        } else if count > 1 {
+            step_record = step_record.bool("ok", true);
+        }
+
+        if count > 1 {
+            steps.push(step_record);
+        } else {
+            record = Some(step_record);
+        }
+    }
+
+    let mut record = record.unwrap_or_else(|| rule_record().u64("steps", count as u64));
+    if let Some(err) = errors.first() {
+        record = record
+            .str("error", &err.to_string())
+            .str("error_kind", err.kind())
+            .u64("errors", errors.len() as u64);
+    }
+
+    // Measure again rather than trusting the pre-clean size, commands may only
+    // prune part of their cache or fail halfway through.
+    let mut freed = None;
+    let mut counted = 0;
+    if is_dry_run {
+        counted = removes.unwrap_or(size);
+    } else if let Some(dir) = &plan.size_dir {
+        let after = glob_size(dir);
+        let shrunk = after < size;
+
+        // What went to the trash still takes up the disk until it is purged.
+        counted = size.saturating_sub(after).saturating_sub(trashed);
+        freed = Some(counted);
+
+        if !shrunk && size > 0 {
+            msg.clear();
+            msg.push_str("<yellow>Warning: <white>Size of ");
+            push_escaped(&mut msg, &tool.name);
+            msg.push_str(" did not shrink <gray>(");
+            msg.push_str(&human_size(after));
+            msg.push_str(")");
+            out.push(msg);
+        }
+
+        record = record.u64("size_after_bytes", after).bool("shrunk", shrunk);
+    }
+
+    record = record.opt_u64("freed_bytes", freed);
+    if trash && !is_dry_run {
+        record = record.u64("trashed_bytes", trashed);
+    }
+    record = record
+        .bool("ok", errors.is_empty())
+        .u64("elapsed_ms", rule_start.elapsed_ms());
+
+    Cleaned {
+        record,
+        steps,
+        outcome: Outcome {
+            name: tool.name.clone(),
+            errors: errors.len(),
+            freed,
+            trashed,
+        },
+        counted,
+    }
+}
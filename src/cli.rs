@@ -1,19 +1,151 @@
+use crate::{files::parse_duration, report::Format, rules::RulesCommands, trash::TrashCommands};
 use std::cmp;
+use std::time::Duration;
 
 pub enum Commands {
     Scan,
     Clean,
     List,
+    Restore,
+    Trash(TrashCommands),
+    Check,
+    Rules(RulesCommands),
     Help,
     Exit,
 }
 
-pub fn find_suggestion(input: &str) -> Option<&'static str> {
-    let commands = ["scan", "clean", "list", "help"];
+pub struct Options {
+    pub is_unsafe: bool,
+    pub is_dry_run: bool,
+    pub assume_yes: bool,
+    pub parallel: bool,
+    /// Moves cleared directories into the trash instead of deleting them.
+    pub trash: bool,
+    pub format: Format,
+    pub jobs: Option<usize>,
+    /// Turns path cleaning into only removing entries unused for this long.
+    pub older_than: Option<Duration>,
+    pub only: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Rules files given with `--rules`, layered on top of the discovered ones in order.
+    pub rules: Vec<String>,
+}
+
+impl Options {
+    /// Whether the rule called `name` passed the include and exclude lists.
+    pub fn selects(&self, name: &str) -> bool {
+        let matches = |list: &Vec<String>| list.iter().any(|n| n.eq_ignore_ascii_case(name));
+
+        (self.only.is_empty() || matches(&self.only)) && !matches(&self.exclude)
+    }
+}
+
+/// Parses everything after the command name. Bare words select rules by name.
+pub fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        is_unsafe: false,
+        is_dry_run: false,
+        assume_yes: false,
+        parallel: false,
+        trash: false,
+        format: Format::Text,
+        jobs: None,
+        older_than: None,
+        only: Vec::new(),
+        exclude: Vec::new(),
+        rules: Vec::new(),
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--unsafe" | "-u" => options.is_unsafe = true,
+            "--dry-run" | "-n" => options.is_dry_run = true,
+            "--yes" | "-y" => options.assume_yes = true,
+            "--parallel" | "-p" => options.parallel = true,
+            "--trash" | "-t" => options.trash = true,
+            "--exclude" | "-x" => match iter.next() {
+                Some(list) => push_names(&mut options.exclude, list),
+                None => return Err("--exclude requires a list of rule names".to_string()),
+            },
+            "--format" => match iter.next() {
+                Some(name) => options.format = parse_format(name)?,
+                None => return Err("--format requires one of text, json or ndjson".to_string()),
+            },
+            "--jobs" | "-j" => match iter.next() {
+                Some(count) => options.jobs = Some(parse_jobs(count)?),
+                None => return Err("--jobs requires a thread count".to_string()),
+            },
+            "--rules" => match iter.next() {
+                Some(path) => options.rules.push(path.to_string()),
+                None => return Err("--rules requires a file".to_string()),
+            },
+            "--older-than" => match iter.next() {
+                Some(age) => options.older_than = Some(parse_age(age)?),
+                None => return Err("--older-than requires an age like 30d".to_string()),
+            },
+            _ => {
+                if let Some(age) = arg.strip_prefix("--older-than=") {
+                    options.older_than = Some(parse_age(age)?);
+                } else if let Some(count) = arg.strip_prefix("--jobs=") {
+                    options.jobs = Some(parse_jobs(count)?);
+                } else if let Some(list) = arg.strip_prefix("--exclude=") {
+                    push_names(&mut options.exclude, list);
+                } else if let Some(path) = arg.strip_prefix("--rules=") {
+                    options.rules.push(path.to_string());
+                } else if let Some(name) = arg.strip_prefix("--format=") {
+                    options.format = parse_format(name)?;
+                } else if arg.starts_with('-') {
+                    return Err(format!("Unknown option '{}'", arg));
+                } else {
+                    push_names(&mut options.only, arg);
+                }
+            }
+        }
+    }
+
+    // Pruning removes single files for good, only whole directories go to the trash.
+    if options.trash && options.older_than.is_some() {
+        return Err(
+            "--trash can't be combined with --older-than, pruned files can't be restored"
+                .to_string(),
+        );
+    }
+
+    Ok(options)
+}
 
-    commands
-        .iter()
-        .map(|&cmd| (levenshtein(input, cmd), cmd))
+fn parse_format(name: &str) -> Result<Format, String> {
+    Format::parse(name).ok_or_else(|| format!("Unknown format '{}'", name))
+}
+
+fn parse_jobs(count: &str) -> Result<usize, String> {
+    match count.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Invalid thread count '{}'", count)),
+    }
+}
+
+fn parse_age(age: &str) -> Result<Duration, String> {
+    parse_duration(age).ok_or_else(|| format!("Invalid age '{}', expected e.g. 12h or 30d", age))
+}
+
+fn push_names(out: &mut Vec<String>, list: &str) {
+    for name in list.split(',') {
+        let name = name.trim();
+        if !name.is_empty() {
+            out.push(name.to_string());
+        }
+    }
+}
+
+pub fn find_suggestion<'a>(
+    input: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|cmd| (levenshtein(input, cmd), cmd))
         .filter(|(dist, _)| *dist < 3)
         .min_by_key(|(dist, _)| *dist)
         .map(|(_, cmd)| cmd)
@@ -25,10 +157,7 @@ fn levenshtein(a: &str, b: &str) -> usize {
         return b_len;
     }
 
-    let mut column: [usize; 16] = [0; 16];
-    for i in 1..=b_len {
-        column[i] = i;
-    }
+    let mut column: Vec<usize> = (0..=b_len).collect();
 
     for (i, char_a) in a.chars().enumerate() {
         let mut last_diagonal = i;
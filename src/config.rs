@@ -1 +1,11 @@
-pub const MAX_RULES: usize = 64;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Worker threads used to walk directories, `0` picks one per available core.
+pub static JOBS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn jobs() -> usize {
+    match JOBS.load(Ordering::Relaxed) {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
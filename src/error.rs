@@ -0,0 +1,68 @@
+use std::fmt;
+use std::io;
+
+/// Why cleaning a rule (or part of it) failed.
+pub enum CleanError {
+    /// The executable of a `RunCommand` could not be found.
+    NotFound(String),
+    PermissionDenied(String),
+    /// The file is locked or used by another process.
+    InUse(String),
+    /// The command ran but exited unsuccessfully.
+    Exit {
+        code: i32,
+        stderr: String,
+    },
+    Io {
+        path: String,
+        kind: io::ErrorKind,
+    },
+    /// A path in the rule could not be expanded, usually an unset variable.
+    Expand(String),
+}
+
+impl CleanError {
+    pub fn from_io(path: &str, err: &io::Error) -> Self {
+        let path = path.to_string();
+
+        // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION on Windows.
+        if cfg!(windows) && matches!(err.raw_os_error(), Some(32) | Some(33)) {
+            return Self::InUse(path);
+        }
+
+        match err.kind() {
+            io::ErrorKind::NotFound => Self::NotFound(path),
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied(path),
+            io::ErrorKind::ResourceBusy | io::ErrorKind::ExecutableFileBusy => Self::InUse(path),
+            kind => Self::Io { path, kind },
+        }
+    }
+
+    /// Short stable identifier used in machine-readable output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NotFound(_) => "not_found",
+            Self::PermissionDenied(_) => "permission_denied",
+            Self::InUse(_) => "in_use",
+            Self::Exit { .. } => "exit_code",
+            Self::Io { .. } => "io",
+            Self::Expand(_) => "expand",
+        }
+    }
+}
+
+impl fmt::Display for CleanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(path) => write!(f, "'{}' not found", path),
+            Self::PermissionDenied(path) => write!(f, "permission denied: '{}'", path),
+            Self::InUse(path) => write!(f, "'{}' is in use by another process", path),
+            Self::Exit { code, stderr } if stderr.is_empty() => {
+                write!(f, "exited with code {}", code)
+            }
+            Self::Exit { code, stderr } => write!(f, "exited with code {}: {}", code, stderr),
+            Self::Io { path, kind } => write!(f, "'{}': {}", path, kind),
+            Self::Expand(message) => write!(f, "{}", message),
+        }
+    }
+}
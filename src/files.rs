@@ -1,25 +1,91 @@
-use crate::minimessage_const::ConstStr;
+use crate::{config::jobs, error::CleanError, minimessage_const::ConstStr};
+use std::collections::VecDeque;
 use std::env;
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 pub fn folder_size(path: &str) -> u64 {
-    let mut size = 0;
+    folder_sizes(&[path])[0]
+}
 
-    if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries.flatten() {
-            if let Ok(metadata) = entry.metadata() {
-                if metadata.is_dir() {
-                    if let Some(path_str) = entry.path().to_str() {
-                        size += folder_size(path_str);
+/// A directory still to be read, and the index of the root it counts towards.
+type SizeJob = (PathBuf, usize);
+
+/// Sizes several trees at once on `jobs()` threads. Every worker takes directories from
+/// the back of its own queue and steals from the front of the others once it runs dry.
+pub fn folder_sizes(paths: &[&str]) -> Vec<u64> {
+    let workers = jobs().max(1);
+    let sizes: Vec<AtomicU64> = paths.iter().map(|_| AtomicU64::new(0)).collect();
+    let queues: Vec<Mutex<VecDeque<SizeJob>>> =
+        (0..workers).map(|_| Mutex::new(VecDeque::new())).collect();
+
+    // Directories queued or being read, the walk is over once this drops to zero.
+    let pending = AtomicUsize::new(paths.len());
+
+    for (i, path) in paths.iter().enumerate() {
+        queues[i % workers]
+            .lock()
+            .unwrap()
+            .push_back((PathBuf::from(path), i));
+    }
+
+    thread::scope(|scope| {
+        for id in 1..workers {
+            let (queues, pending, sizes) = (&queues, &pending, &sizes);
+            scope.spawn(move || size_worker(id, queues, pending, sizes));
+        }
+        size_worker(0, &queues, &pending, &sizes);
+    });
+
+    sizes.into_iter().map(AtomicU64::into_inner).collect()
+}
+
+fn size_worker(
+    id: usize,
+    queues: &[Mutex<VecDeque<SizeJob>>],
+    pending: &AtomicUsize,
+    sizes: &[AtomicU64],
+) {
+    loop {
+        // Own queue first, in a statement of its own so its lock is released before
+        // stealing. Holding it while locking another queue deadlocks two idle workers.
+        let own = queues[id].lock().unwrap().pop_back();
+        let job = own.or_else(|| {
+            (1..queues.len())
+                .map(|offset| (id + offset) % queues.len())
+                .find_map(|victim| queues[victim].lock().unwrap().pop_front())
+        });
+
+        let Some((dir, root)) = job else {
+            if pending.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            thread::sleep(Duration::from_micros(50));
+            continue;
+        };
+
+        let mut bytes = 0;
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                if let Ok(metadata) = entry.metadata() {
+                    if metadata.is_dir() {
+                        pending.fetch_add(1, Ordering::AcqRel);
+                        queues[id].lock().unwrap().push_back((entry.path(), root));
+                    } else {
+                        bytes += metadata.len();
                     }
-                } else {
-                    size += metadata.len();
                 }
             }
         }
+
+        sizes[root].fetch_add(bytes, Ordering::Relaxed);
+        pending.fetch_sub(1, Ordering::AcqRel);
     }
-    size
 }
 
 pub fn human_size(bytes: u64) -> ConstStr<16> {
@@ -52,26 +118,432 @@ pub fn human_size(bytes: u64) -> ConstStr<16> {
     line
 }
 
-pub fn clear_dir(path: &str) {
+/// Parses durations like `90s`, `45m`, `12h`, `30d` or `2w`.
+pub fn parse_duration(input: &str) -> Option<Duration> {
+    let input = input.trim();
+    let split = input.find(|c: char| !c.is_ascii_digit())?;
+    let value: u64 = input[..split].parse().ok()?;
+
+    let unit = match &input[split..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    value.checked_mul(unit).map(Duration::from_secs)
+}
+
+/// Parses sizes like `512MB`, `1.5GB` or `800K`, in the same 1024 based units as `human_size`.
+pub fn parse_size(input: &str) -> Option<u64> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        _ => return None,
+    };
+
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let whole: u64 = whole.parse().ok()?;
+    let mut bytes = whole.checked_mul(multiplier)?;
+
+    // Up to three decimals is more precision than anyone writes in a budget.
+    if !fraction.is_empty() {
+        let digits = &fraction[..fraction.len().min(3)];
+        let scale = 10u64.pow(digits.len() as u32);
+        let fraction: u64 = digits.parse().ok()?;
+        bytes = bytes.checked_add(multiplier * fraction / scale)?;
+    }
+
+    Some(bytes)
+}
+
+/// Formats seconds in the largest unit that divides them, the inverse of `parse_duration`.
+pub fn human_duration(secs: u64) -> ConstStr<24> {
+    const UNITS: [(u64, &str); 4] = [
+        (7 * 24 * 60 * 60, "w"),
+        (24 * 60 * 60, "d"),
+        (60 * 60, "h"),
+        (60, "m"),
+    ];
+
+    let mut out = ConstStr::<24>::new();
+    for (size, unit) in UNITS {
+        if secs > 0 && secs.is_multiple_of(size) {
+            out.push_u64(secs / size);
+            out.push_str(unit);
+            return out;
+        }
+    }
+
+    out.push_u64(secs);
+    out.push_u8(b's');
+    out
+}
+
+/// Removes everything inside `path`, keeping `path` itself. Keeps going after a failure
+/// and returns every entry that could not be removed. A missing `path` is nothing to clean.
+pub fn clear_dir(path: &str) -> Result<(), Vec<CleanError>> {
+    let mut errors = Vec::new();
+    clear_dir_into(path, &mut errors);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn clear_dir_into(path: &str, errors: &mut Vec<CleanError>) {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+        Err(err) => {
+            errors.push(CleanError::from_io(path, &err));
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let path_str = path.to_str().unwrap_or("?");
+
+        // A link is removed itself, never followed into data outside the cache.
+        let result = match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => {
+                clear_dir_into(path_str, errors);
+                fs::remove_dir(&path)
+            }
+            Ok(metadata) if metadata.is_symlink() => remove_link(&path),
+            Ok(_) => fs::remove_file(&path),
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(()) => {}
+            // Already gone, or a directory that kept a child we failed to remove above.
+            Err(err)
+                if err.kind() == io::ErrorKind::NotFound
+                    || err.kind() == io::ErrorKind::DirectoryNotEmpty => {}
+            Err(err) => errors.push(CleanError::from_io(path_str, &err)),
+        }
+    }
+}
+
+/// Windows removes a link to a directory like a directory.
+fn remove_link(path: &Path) -> io::Result<()> {
+    fs::remove_file(path).or_else(|err| fs::remove_dir(path).map_err(|_| err))
+}
+
+/// Removes files under `path` that were neither modified nor accessed since `cutoff`,
+/// then any directories that were left empty. With `dry_run` nothing is touched and
+/// `visit` only sees what would be removed.
+pub fn prune_older_than(
+    path: &str,
+    cutoff: SystemTime,
+    dry_run: bool,
+    visit: &mut impl FnMut(&Path, &Metadata),
+) -> Result<(), Vec<CleanError>> {
+    let mut errors = Vec::new();
+    prune_older_than_into(Path::new(path), cutoff, dry_run, visit, &mut errors);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Returns whether `dir` is (or would be) empty afterwards, and whether anything in it was
+/// removed. Directories that were already empty are only pruned once they are old too.
+fn prune_older_than_into(
+    dir: &Path,
+    cutoff: SystemTime,
+    dry_run: bool,
+    visit: &mut impl FnMut(&Path, &Metadata),
+    errors: &mut Vec<CleanError>,
+) -> (bool, bool) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            if err.kind() != io::ErrorKind::NotFound {
+                errors.push(CleanError::from_io(dir.to_str().unwrap_or("?"), &err));
+            }
+            return (false, false);
+        }
+    };
+
+    let mut empty = true;
+    let mut removed_any = false;
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // A link is judged and removed as itself, never followed out of the cache.
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            empty = false;
+            continue;
+        };
+
+        let removable = if metadata.is_dir() {
+            let (emptied, removed) = prune_older_than_into(&path, cutoff, dry_run, visit, errors);
+            emptied && (removed || last_used(&metadata) < cutoff)
+        } else {
+            last_used(&metadata) < cutoff
+        };
+
+        if !removable {
+            empty = false;
+            continue;
+        }
+
+        visit(&path, &metadata);
+        if dry_run {
+            removed_any = true;
+            continue;
+        }
+
+        let result = if metadata.is_dir() {
+            fs::remove_dir(&path)
+        } else if metadata.is_symlink() {
+            remove_link(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+
+        match result {
+            Ok(()) => removed_any = true,
+            Err(err) => {
+                empty = false;
+                if err.kind() != io::ErrorKind::NotFound {
+                    errors.push(CleanError::from_io(path.to_str().unwrap_or("?"), &err));
+                }
+            }
+        }
+    }
+
+    (empty, removed_any)
+}
+
+/// The later of the modification and access time.
+pub fn last_used(metadata: &Metadata) -> SystemTime {
+    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    match metadata.accessed() {
+        Ok(accessed) if accessed > modified => accessed,
+        _ => modified,
+    }
+}
+
+/// Deletes the least recently used files under `path` until the tree fits in `budget`
+/// bytes, then removes directories left empty. `visit` sees every evicted file; with
+/// `dry_run` nothing is touched. Returns the size of the tree afterwards.
+pub fn trim_to_budget(
+    path: &str,
+    budget: u64,
+    dry_run: bool,
+    visit: &mut impl FnMut(&Path, &Metadata),
+) -> Result<u64, Vec<CleanError>> {
+    let mut files: Vec<(PathBuf, Metadata)> = Vec::new();
+    collect_files(Path::new(path), &mut files);
+
+    let mut total: u64 = files.iter().map(|(_, metadata)| metadata.len()).sum();
+    files.sort_by_key(|(_, metadata)| last_used(metadata));
+
+    let mut errors = Vec::new();
+    let mut emptied: Vec<PathBuf> = Vec::new();
+
+    for (file, metadata) in &files {
+        if total <= budget {
+            break;
+        }
+
+        if !dry_run {
+            if let Err(err) = fs::remove_file(file) {
+                if err.kind() != io::ErrorKind::NotFound {
+                    errors.push(CleanError::from_io(file.to_str().unwrap_or("?"), &err));
+                    continue;
+                }
+            }
+
+            if let Some(parent) = file.parent() {
+                emptied.push(parent.to_path_buf());
+            }
+        }
+
+        visit(file, metadata);
+        total -= metadata.len();
+    }
+
+    // Deepest first, so a parent only goes once its children did. Removing a directory
+    // that still has files in it just fails, which is what we want.
+    emptied.sort_by(|a, b| {
+        b.components()
+            .count()
+            .cmp(&a.components().count())
+            .then_with(|| a.cmp(b))
+    });
+    emptied.dedup();
+
+    let root = Path::new(path);
+    for dir in emptied {
+        for ancestor in dir.ancestors() {
+            if ancestor == root || !ancestor.starts_with(root) || fs::remove_dir(ancestor).is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(total)
+    } else {
+        Err(errors)
+    }
+}
+
+fn collect_files(dir: &Path, out: &mut Vec<(PathBuf, Metadata)>) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if let Ok(metadata) = entry.metadata() {
+                if metadata.is_dir() {
+                    collect_files(&entry.path(), out);
+                } else {
+                    out.push((entry.path(), metadata));
+                }
+            }
+        }
+    }
+}
+
+/// Visits every entry `clear_dir` would remove, in the same order it removes them.
+pub fn walk_dir(path: &str, visit: &mut impl FnMut(&Path, &Metadata)) {
     if let Ok(entries) = fs::read_dir(path) {
         for entry in entries.flatten() {
             let path = entry.path();
-            if let Ok(metadata) = path.metadata() {
+            // Not following links, `clear_dir` removes a link rather than what it points to.
+            if let Ok(metadata) = fs::symlink_metadata(&path) {
                 if metadata.is_dir() {
                     if let Some(path_str) = path.to_str() {
-                        clear_dir(path_str);
+                        walk_dir(path_str, visit);
                     }
-                    let _ = fs::remove_dir(&path);
-                } else {
-                    let _ = fs::remove_file(&path);
                 }
+                visit(&path, &metadata);
             }
         }
     }
 }
 
-pub fn expand_path(input: ConstStr<260>) -> ConstStr<260> {
-    let input_str = input.as_str();
+/// Expands environment variables and then a leading `~`. Variables that are unset or
+/// empty are an error, a missing `$GOCACHE` must not turn into `/`.
+pub fn expand_path(input: &str) -> Result<String, CleanError> {
+    let expanded = expand_vars(input, &|name| env::var(name).ok().filter(|v| !v.is_empty()))
+        .map_err(|(_, message)| CleanError::Expand(message))?;
+
+    Ok(expand_home(&expanded))
+}
+
+fn is_var_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// Replaces `$VAR`, `${VAR}`, `${VAR:-default}` and `%VAR%` using `lookup`. Errors carry
+/// the byte offset of the variable in `input`.
+pub fn expand_vars(
+    input: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, (usize, String)> {
+    let bytes = input.as_bytes();
+    let mut out = String::with_capacity(input.len());
+    let mut literal = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let (name, default, end) = match bytes[i] {
+            b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                // Find the matching brace, defaults may hold variables of their own.
+                let mut depth = 0;
+                let Some(close) = (i + 1..bytes.len()).find(|&j| {
+                    match bytes[j] {
+                        b'{' => depth += 1,
+                        b'}' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                }) else {
+                    return Err((i, "unclosed `${`".to_string()));
+                };
+
+                let inner = &input[i + 2..close];
+                let (name, default) = match inner.split_once(":-") {
+                    Some((name, default)) => (name, Some((default, i + 2 + name.len() + 2))),
+                    None => (inner, None),
+                };
+
+                if name.is_empty() || !name.bytes().all(is_var_char) {
+                    return Err((i, format!("invalid variable name `{}`", name)));
+                }
+                (name, default, close + 1)
+            }
+            b'$' if bytes
+                .get(i + 1)
+                .is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_') =>
+            {
+                let end = (i + 1..bytes.len())
+                    .find(|&j| !is_var_char(bytes[j]))
+                    .unwrap_or(bytes.len());
+                (&input[i + 1..end], None, end)
+            }
+            b'%' => {
+                // Windows style, `%ProgramFiles(x86)%` included. Anything else is a plain `%`.
+                let end = (i + 1..bytes.len())
+                    .find(|&j| !(is_var_char(bytes[j]) || bytes[j] == b'(' || bytes[j] == b')'));
+                match end {
+                    Some(end) if end > i + 1 && bytes[end] == b'%' => {
+                        (&input[i + 1..end], None, end + 1)
+                    }
+                    _ => {
+                        i += 1;
+                        continue;
+                    }
+                }
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let value = match (lookup(name), default) {
+            (Some(value), _) => value,
+            (None, Some((default, offset))) => {
+                expand_vars(default, lookup).map_err(|(o, message)| (offset + o, message))?
+            }
+            (None, None) => {
+                return Err((i, format!("environment variable `{}` is not set", name)));
+            }
+        };
+
+        out.push_str(&input[literal..i]);
+        out.push_str(&value);
+        i = end;
+        literal = end;
+    }
+
+    out.push_str(&input[literal..]);
+    Ok(out)
+}
+
+/// Replaces a leading `~` with the home directory.
+pub fn expand_home(input_str: &str) -> String {
     let mut result = PathBuf::new();
 
     if input_str.starts_with('~') {
@@ -88,26 +560,242 @@ pub fn expand_path(input: ConstStr<260>) -> ConstStr<260> {
         }
 
         if input_str.len() > 1 {
-            if input_str.starts_with("~/") {
-                result.push(&input_str[2..]);
-            } else if input_str.starts_with("~\\") {
-                result.push(&input_str[2..]);
-            } else {
-                result.push(&input_str[1..]);
-            }
+            let rest = input_str
+                .strip_prefix("~/")
+                .or_else(|| input_str.strip_prefix("~\\"))
+                .or_else(|| input_str.strip_prefix('~'))
+                .unwrap_or(input_str);
+            result.push(rest);
         }
     } else {
         result.push(input_str);
     }
 
-    let mut out = ConstStr::<260>::new();
-    if let Some(path_str) = result.to_str() {
-        out.push_str(path_str);
+    result.to_str().unwrap_or("").to_string()
+}
+
+/// Whether `path` has any glob syntax in it.
+pub fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Checks the syntax of a glob pattern. Errors carry the byte offset of the problem.
+pub fn check_pattern(path: &str) -> Result<(), (usize, String)> {
+    let offsets: Vec<usize> = path.char_indices().map(|(i, _)| i).collect();
+    let pattern: Vec<char> = path.chars().collect();
+
+    let mut i = 0;
+    while i < pattern.len() {
+        if pattern[i] == '[' {
+            match match_class(&pattern, i, ' ') {
+                Some((_, end)) => i = end,
+                None => return Err((offsets[i], "unclosed `[`".to_string())),
+            }
+        }
+        i += 1;
     }
-    out
+
+    for (offset, component) in path_components(path) {
+        if component.contains("**") && component != "**" {
+            return Err((offset, "`**` must be a whole path component".to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits on `/`, and on `\` too on Windows, with the byte offset of each component.
+fn path_components(path: &str) -> Vec<(usize, &str)> {
+    let mut components = Vec::new();
+    let mut start = 0;
+
+    for (i, c) in path.char_indices() {
+        if c == '/' || (cfg!(windows) && c == '\\') {
+            components.push((start, &path[start..i]));
+            start = i + 1;
+        }
+    }
+    components.push((start, &path[start..]));
+
+    components
+}
+
+/// Matches `c` against the class starting at `pattern[start] == '['`. Returns whether it
+/// matched and the index of the closing `]`, or `None` if the class is never closed.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let first = i;
+    while i < pattern.len() {
+        // A `]` right after the opening bracket is a literal.
+        if pattern[i] == ']' && i > first {
+            return Some((matched != negate, i));
+        }
+
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&e| e != ']') {
+            matched |= pattern[i] <= c && c <= pattern[i + 2];
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+    }
+
+    None
+}
+
+/// Matches a file name against one component of a pattern.
+fn match_name(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, so a failed match can retry with it eating one more char.
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        let next = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match match_class(pattern, p, name[n]) {
+                Some((true, end)) => Some(end + 1),
+                Some((false, _)) => None,
+                None => (name[n] == '[').then_some(p + 1),
+            },
+            Some(&c) => (c == name[n]).then_some(p + 1),
+            None => None,
+        };
+
+        match (next, star) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            (None, Some((star_p, star_n))) => {
+                p = star_p + 1;
+                n = star_n + 1;
+                star = Some((star_p, star_n + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Every existing path matching `pattern`, sorted. A path without glob syntax is returned
+/// as it is, whether it exists or not.
+pub fn expand_glob(pattern: &str) -> Vec<String> {
+    if !is_pattern(pattern) {
+        return vec![pattern.to_string()];
+    }
+
+    // Everything before the first component with glob syntax is taken literally.
+    let components = path_components(pattern);
+    let literal = components
+        .iter()
+        .take_while(|(_, c)| !is_pattern(c))
+        .count();
+    let base = match components.get(literal) {
+        Some(&(offset, _)) => &pattern[..offset],
+        None => pattern,
+    };
+    let rest: Vec<&str> = components[literal..]
+        .iter()
+        .map(|&(_, c)| c)
+        .filter(|c| !c.is_empty())
+        .collect();
+
+    let mut matches = Vec::new();
+    glob_walk(PathBuf::from(base), &rest, &mut matches);
+
+    matches.sort();
+    matches.dedup();
+    matches
+        .into_iter()
+        .filter_map(|path| path.to_str().map(str::to_string))
+        .collect()
 }
 
-pub fn find_executable(name: &str) -> Option<ConstStr<260>> {
+fn glob_walk(dir: PathBuf, components: &[&str], out: &mut Vec<PathBuf>) {
+    let Some((&component, rest)) = components.split_first() else {
+        out.push(dir);
+        return;
+    };
+
+    if !is_pattern(component) {
+        let next = dir.join(component);
+        if rest.is_empty() && next.exists() {
+            out.push(next);
+        } else if next.is_dir() {
+            glob_walk(next, rest, out);
+        }
+        return;
+    }
+
+    // An empty base is the current directory, joined paths then stay relative.
+    let read_from = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        &dir
+    };
+    let Ok(entries) = fs::read_dir(read_from) else {
+        return;
+    };
+
+    if component == "**" {
+        // Zero directories deep, then one more for every subdirectory. Symlinks are not
+        // followed so links back up the tree can't loop.
+        glob_walk(dir.clone(), rest, out);
+
+        for entry in entries.flatten() {
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            let name = entry.file_name();
+            if is_dir && !name.to_str().is_some_and(|n| n.starts_with('.')) {
+                glob_walk(dir.join(name), components, out);
+            }
+        }
+        return;
+    }
+
+    let pattern: Vec<char> = component.chars().collect();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+
+        // Like shells, wildcards don't match hidden entries unless asked to.
+        if name.starts_with('.') && !component.starts_with('.') {
+            continue;
+        }
+        if !match_name(&pattern, &name.chars().collect::<Vec<_>>()) {
+            continue;
+        }
+
+        let path = dir.join(name);
+        if rest.is_empty() {
+            out.push(path);
+        } else if path.is_dir() {
+            glob_walk(path, rest, out);
+        }
+    }
+}
+
+/// The combined size of every match of `pattern`.
+pub fn glob_size(pattern: &str) -> u64 {
+    let matches = expand_glob(pattern);
+    let roots: Vec<&str> = matches.iter().map(String::as_str).collect();
+    folder_sizes(&roots).into_iter().sum()
+}
+
+pub fn find_executable(name: &str) -> Option<String> {
     if let Ok(path_env) = env::var("PATH") {
         for dir in path_env.split(if cfg!(windows) { ';' } else { ':' }) {
             let mut exe_path = PathBuf::from(dir);
@@ -124,9 +812,7 @@ pub fn find_executable(name: &str) -> Option<ConstStr<260>> {
 
                     if exe_path.exists() {
                         if let Some(path_str) = exe_path.to_str() {
-                            let mut result = ConstStr::<260>::new();
-                            result.push_str(path_str);
-                            return Some(result);
+                            return Some(path_str.to_string());
                         }
                     }
 
@@ -136,9 +822,7 @@ pub fn find_executable(name: &str) -> Option<ConstStr<260>> {
                 exe_path.push(name);
                 if exe_path.exists() {
                     if let Some(path_str) = exe_path.to_str() {
-                        let mut result = ConstStr::<260>::new();
-                        result.push_str(path_str);
-                        return Some(result);
+                        return Some(path_str.to_string());
                     }
                 }
             }
@@ -146,3 +830,162 @@ pub fn find_executable(name: &str) -> Option<ConstStr<260>> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_sizes_nested_tree() {
+        let root = env::temp_dir().join(format!("nil-size-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        // Two roots, each 10 wide and 3 deep with a file of 10 bytes in every directory.
+        let mut expected = [0, 0];
+        for (i, expected) in expected.iter_mut().enumerate() {
+            for a in 0..10 {
+                for b in 0..10 {
+                    let dir = root
+                        .join(i.to_string())
+                        .join(a.to_string())
+                        .join(b.to_string());
+                    fs::create_dir_all(&dir).unwrap();
+                    fs::write(dir.join("file"), [0; 10]).unwrap();
+                    *expected += 10;
+                }
+            }
+        }
+
+        let paths = [root.join("0"), root.join("1")];
+        let paths: Vec<&str> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+        crate::config::JOBS.store(8, Ordering::Relaxed);
+        for _ in 0..50 {
+            assert_eq!(folder_sizes(&paths), expected);
+        }
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("45m"), Some(Duration::from_secs(45 * 60)));
+        assert_eq!(
+            parse_duration(" 12h "),
+            Some(Duration::from_secs(12 * 3600))
+        );
+        assert_eq!(parse_duration("2w"), Some(Duration::from_secs(14 * 86400)));
+        assert_eq!(parse_duration("0d"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn parse_duration_invalid() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("d"), None);
+        assert_eq!(parse_duration("1.5h"), None);
+        assert_eq!(parse_duration("3 d"), None);
+        assert_eq!(parse_duration("5y"), None);
+        assert_eq!(parse_duration("99999999999999999999s"), None);
+        assert_eq!(parse_duration("18446744073709551615w"), None);
+    }
+
+    #[test]
+    fn human_duration_round_trips() {
+        for input in ["90s", "45m", "12h", "30d", "2w"] {
+            let secs = parse_duration(input).unwrap().as_secs();
+            assert_eq!(human_duration(secs).as_str(), input);
+        }
+    }
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("10"), Some(10));
+        assert_eq!(parse_size("10B"), Some(10));
+        assert_eq!(parse_size("800K"), Some(800 << 10));
+        assert_eq!(parse_size("512mb"), Some(512 << 20));
+        assert_eq!(parse_size(" 2 GB "), Some(2 << 30));
+        assert_eq!(parse_size("1.5GB"), Some(3 << 29));
+        assert_eq!(parse_size("1.0005KB"), Some(1024));
+        assert_eq!(parse_size("1TB"), Some(1 << 40));
+    }
+
+    #[test]
+    fn parse_size_invalid() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("GB"), None);
+        assert_eq!(parse_size("5XB"), None);
+        assert_eq!(parse_size("1.2.3MB"), None);
+        assert_eq!(parse_size("-1MB"), None);
+        assert_eq!(parse_size("99999999999TB"), None);
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/me".to_string()),
+            "CACHE" => Some("/var/cache".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expand_vars_forms() {
+        let expand = |input: &str| expand_vars(input, &lookup);
+        assert_eq!(expand("$HOME/x").unwrap(), "/home/me/x");
+        assert_eq!(expand("${HOME}x").unwrap(), "/home/mex");
+        assert_eq!(expand("%CACHE%\\y").unwrap(), "/var/cache\\y");
+        assert_eq!(expand("100% sure").unwrap(), "100% sure");
+        assert_eq!(expand("a$").unwrap(), "a$");
+    }
+
+    #[test]
+    fn expand_vars_defaults() {
+        let expand = |input: &str| expand_vars(input, &lookup);
+        assert_eq!(expand("${CACHE:-/tmp}/pip").unwrap(), "/var/cache/pip");
+        assert_eq!(expand("${UNSET:-/tmp}/pip").unwrap(), "/tmp/pip");
+        assert_eq!(expand("${UNSET:-$HOME/.cache}").unwrap(), "/home/me/.cache");
+        assert_eq!(expand("${UNSET:-}x").unwrap(), "x");
+    }
+
+    #[test]
+    fn expand_vars_errors() {
+        let expand = |input: &str| expand_vars(input, &lookup);
+        assert_eq!(expand("a/$UNSET/b").unwrap_err().0, 2);
+        assert_eq!(expand("%UNSET%").unwrap_err().0, 0);
+        // Offsets inside a default point into the whole input.
+        assert_eq!(expand("${UNSET:-x/$ALSO}").unwrap_err().0, 11);
+        assert_eq!(expand("ab${HOME").unwrap_err().0, 2);
+        assert_eq!(expand("${A-B}").unwrap_err().0, 0);
+    }
+
+    fn glob(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        match_name(&pattern, &name)
+    }
+
+    #[test]
+    fn glob_star_and_question() {
+        assert!(glob("*", "anything"));
+        assert!(glob("*", ""));
+        assert!(glob("*.log", "a.b.log"));
+        assert!(!glob("*.log", "a.log.txt"));
+        assert!(glob("a*b*c", "aXbYbZc"));
+        assert!(glob("?.txt", "a.txt"));
+        assert!(!glob("?.txt", "ab.txt"));
+        assert!(!glob("?", ""));
+    }
+
+    #[test]
+    fn glob_classes() {
+        assert!(glob("[abc]x", "bx"));
+        assert!(!glob("[abc]x", "dx"));
+        assert!(glob("v[0-9]", "v7"));
+        assert!(glob("[!a-c]", "d"));
+        assert!(!glob("[!a-c]", "b"));
+        assert!(glob("[]]", "]"));
+        assert!(glob("[[]", "["));
+        // An unclosed class is a literal `[`.
+        assert!(glob("[ab", "[ab"));
+    }
+}
@@ -1,27 +1,41 @@
 #![feature(const_trait_impl)]
 #![feature(const_cmp)]
 
-use std::{env, process::exit};
+use std::{env, process::exit, sync::atomic::Ordering};
 
 use crate::{
-    cli::{find_suggestion, Commands},
-    config::MAX_RULES,
-    files::{clear_dir, expand_path, folder_size, human_size},
+    cli::{find_suggestion, parse_options, Commands, Options},
+    config::JOBS,
+    error::CleanError,
+    files::{expand_glob, folder_sizes, human_size, is_pattern},
     minimessage_const::{serialize, ConstStr},
-    r#abstract::Timer,
-    rules::{is_rule_active, load_rules, CacheRule, CleanMethod, DetectMethod},
-    ui::{print_styled, println},
+    report::{Record, Report},
+    rules::{
+        is_rule_active, load_rules, rule_sources, CacheRule, RulesCommands, SizeDir, BUILTIN_RULES,
+    },
+    trash::TrashCommands,
+    ui::{eprint_styled, print_styled, println, push_escaped},
 };
 
 mod r#abstract;
+mod check;
+mod clean;
 mod cli;
 mod config;
+mod error;
 mod files;
 mod minimessage_const;
+mod parser;
+mod regex;
+mod report;
 mod rules;
+mod trash;
 mod ui;
 
-const HELP_MESSAGE: &str = serialize::<1024>(concat!(
+/// Room for the help text once styled, every color adds an escape code.
+const HELP_CAPACITY: usize = 4096;
+
+const HELP: ConstStr<HELP_CAPACITY> = serialize::<HELP_CAPACITY>(concat!(
     r#"<#55AAFF>nil</#55AAFF> <white>v"#,
     env!("CARGO_PKG_VERSION"),
     r#"</white>
@@ -29,68 +43,188 @@ const HELP_MESSAGE: &str = serialize::<1024>(concat!(
     env!("CARGO_PKG_DESCRIPTION"),
     r#"</#A5FAFF>
 
-<white><b><u>Usage:</u></b> <#55AAFF><b>nil</b> \<COMMAND> [OPTION]</#55AAFF>
+<white><b><u>Usage:</u></b> <#55AAFF><b>nil</b> \<COMMAND> [OPTION] [RULE]...</#55AAFF>
 
 <white><b><u>Commands:</u></b></white>
   <b>s, scan </b>        Scans the caches and shows the sizes of them.
   <b>c, clean</b>        Cleans the caches.
   <b>l, list </b>        Lists caches found.
+  <b>restore </b>        Puts caches cleaned with <b>--trash</b> back.
+  <b>trash   </b>        Lists the trash, or purges it with <b>trash purge</b>.
+  <b>check [PATH]</b>    Validates rules files, the loaded ones by default.
+  <b>rules dump  </b>    Prints the built-in rules to start a rules file from.
   <b>help    </b>        Prints the help message.
 
 <b><u>Options:</u></b>
   <b>-h, --help   </b>   Print help
   <b>-v, --version</b>   Print version
+  <b>--format     </b>   Output format: text, json or ndjson
+  <b>-j, --jobs   </b>   Number of threads used to measure caches
+  <b>--rules \<FILE></b> Loads FILE on top of the discovered rules files
 
 <b><u>Subcommand Options:</u></b>
+  <b>scan, clean:</b>
+    <b>[RULE]...   </b>  Only operates on the named rules.
+    <b>--exclude, -x</b> Skips a comma separated list of rules.
   <b>clean:</b>
     <b>--unsafe, -u</b>  Cleans the cache directory instead of using the preferred
                   method.
+    <b>--dry-run, -n</b> Shows what would be removed or run without touching
+                  anything.
+    <b>--yes, -y    </b> Cleans without asking for confirmation.
+    <b>--older-than \<AGE></b>
+                  Only removes files unused for longer than AGE (e.g. 30d).
+    <b>--trash, -t  </b> Moves cleared directories to the trash so they can be
+                  restored.
+    <b>--parallel, -p</b>
+                  Cleans independent caches at the same time, using up to
+                  <b>--jobs</b> threads.
+  <b>restore:</b>
+    <b>[RULE]...   </b>  Only restores the named rules.
+  <b>trash purge:</b>
+    <b>--older-than \<AGE></b>
+                  Only purges what was trashed longer than AGE ago.
 "#
-))
-.as_str();
+));
+
+// `serialize` silently cuts off whatever does not fit, so fail the build instead.
+const _: () = assert!(
+    HELP.len < HELP_CAPACITY,
+    "the help text no longer fits, raise HELP_CAPACITY"
+);
+
+const HELP_MESSAGE: &str = HELP.as_str();
 
 fn print_help() {
     println(HELP_MESSAGE);
 }
 
+/// Exits with an error if a rule named on the command line does not exist.
+fn check_rule_names(options: &Options, rules: &[CacheRule]) {
+    let mut unknown = false;
+
+    for name in options.only.iter().chain(options.exclude.iter()) {
+        if rules.iter().any(|r| r.name.eq_ignore_ascii_case(name)) {
+            continue;
+        }
+
+        let mut msg = ConstStr::<256>::from("<red>Error: <white>Unknown rule '<yellow>");
+        push_escaped(&mut msg, name);
+        msg.push_str("<white>'.");
+        eprint_styled(msg);
+
+        if let Some(suggestion) = find_suggestion(name, rules.iter().map(|r| r.name.as_str())) {
+            msg.clear();
+            msg.push_str("  Did you mean '<yellow>");
+            push_escaped(&mut msg, suggestion);
+            msg.push_str("<white>'?");
+            eprint_styled(msg);
+        }
+
+        unknown = true;
+    }
+
+    if unknown {
+        exit(1);
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     let cmd = if args.len() > 1 { &args[1] } else { "help" };
 
-    let is_unsafe = args.contains(&"--unsafe".to_string()) || args.contains(&"-u".to_string());
-
     let command = match cmd {
         "scan" | "s" => Commands::Scan,
         "clean" | "c" => Commands::Clean,
         "list" | "l" => Commands::List,
+        "restore" => Commands::Restore,
+        "check" => Commands::Check,
+        "rules" => match args.get(2).map(String::as_str) {
+            Some("dump") => Commands::Rules(RulesCommands::Dump),
+            _ => {
+                eprint_styled("<red>Error: <white>Unknown rules command, expected dump.");
+                Commands::Exit
+            }
+        },
+        "trash" => match args.get(2).map(String::as_str) {
+            Some("list") | None => Commands::Trash(TrashCommands::List),
+            Some(arg) if arg.starts_with('-') => Commands::Trash(TrashCommands::List),
+            Some("purge") => Commands::Trash(TrashCommands::Purge),
+            Some(_) => {
+                eprint_styled("<red>Error: <white>Unknown trash command, expected list or purge.");
+                Commands::Exit
+            }
+        },
         "help" | "-h" | "--help" => Commands::Help,
         "version" | "-v" | "--version" => {
             println(env!("CARGO_PKG_VERSION"));
             Commands::Exit
         }
         _ => {
-            print_styled("<red>Error: <white>Unknown command.");
-            if let Some(suggestion) = find_suggestion(cmd) {
+            eprint_styled("<red>Error: <white>Unknown command.");
+            if let Some(suggestion) = find_suggestion(
+                cmd,
+                [
+                    "scan", "clean", "list", "restore", "trash", "check", "rules", "help",
+                ],
+            ) {
                 let mut msg = ConstStr::<256>::from("  Did you mean '<yellow>");
                 msg.push_str(suggestion);
                 msg.push_str("<white>'?");
-                print_styled(&msg);
+                eprint_styled(msg);
             }
 
             Commands::Exit
         }
     };
 
-    const BLANK_RULE: CacheRule = CacheRule {
-        name: ConstStr::new(),
-        detect: DetectMethod::PathExists(ConstStr::new()),
-        clean: CleanMethod::CleanPath(ConstStr::new()),
-        size_dir: None,
+    // `trash` and `rules` take a subcommand before their options, `trash` may leave it out.
+    let has_subcommand = args.get(2).is_some_and(|arg| !arg.starts_with('-'));
+    let options_start =
+        if let (Commands::Trash(_) | Commands::Rules(_), true) = (&command, has_subcommand) {
+            3
+        } else {
+            2
+        };
+    let options = match parse_options(args.get(options_start..).unwrap_or(&[])) {
+        Ok(options) => options,
+        Err(err) => {
+            let mut msg = ConstStr::<256>::from("<red>Error: <white>");
+            push_escaped(&mut msg, &err);
+            msg.push_u8(b'.');
+            eprint_styled(msg);
+            exit(1);
+        }
     };
 
-    let mut rules: [CacheRule; MAX_RULES] = [BLANK_RULE; MAX_RULES];
-    let rule_count = load_rules(&mut rules);
+    let mut report = Report::new(options.format);
+
+    // Runs before loading, a broken rules file is exactly what it reports on.
+    if let Commands::Check = command {
+        let files = if options.only.is_empty() {
+            rule_sources(&options.rules)
+        } else {
+            options.only.clone()
+        };
+        check::run(&files, &mut report);
+        exit(0);
+    }
+
+    if let Commands::Rules(RulesCommands::Dump) = command {
+        println(BUILTIN_RULES.trim_end());
+        exit(0);
+    }
+
+    let rules = load_rules(&options.rules);
+
+    if let Commands::Scan | Commands::Clean = command {
+        check_rule_names(&options, &rules);
+    }
+
+    if let Some(jobs) = options.jobs {
+        JOBS.store(jobs, Ordering::Relaxed);
+    }
 
     match command {
         Commands::Exit => {
@@ -100,37 +234,101 @@ fn main() {
             print_help();
         }
         Commands::List => {
-            println("Detected tools:");
+            report.text("<white>Detected tools:");
 
-            for i in 0..rule_count {
-                let r = &rules[i];
-                if is_rule_active(r) {
-                    let mut s = ConstStr::<64>::from("  <gray>- <yellow>");
-                    s.push_str(r.name.as_str());
+            for r in &rules {
+                let active = is_rule_active(r);
 
-                    print_styled(s.as_str());
+                if report.is_text() {
+                    if active {
+                        let mut s = ConstStr::<256>::from("  <gray>- <yellow>");
+                        push_escaped(&mut s, &r.name);
+
+                        print_styled(s.as_str());
+                    }
+                } else {
+                    report.record(
+                        Record::new("rule")
+                            .str("name", &r.name)
+                            .bool("active", active),
+                    );
                 }
             }
+
+            report.finish();
         }
         Commands::Scan => {
             let mut total: u64 = 0;
 
-            print_styled("<white>Tool Sizes:\n");
+            report.text("<white>Tool Sizes:\n");
+
+            let active: Vec<&CacheRule> = rules
+                .iter()
+                .filter(|t| options.selects(&t.name) && is_rule_active(t))
+                .collect();
+
+            // Size every rule in one go so large trees are walked side by side.
+            let dirs: Vec<Option<Result<String, CleanError>>> = active
+                .iter()
+                .map(|t| t.size_dir.as_ref().map(SizeDir::resolve))
+                .collect();
+            // A glob size dir is sized as each of its matches.
+            let matches: Vec<Vec<String>> = dirs
+                .iter()
+                .map(|dir| match dir {
+                    Some(Ok(dir)) => expand_glob(dir),
+                    _ => Vec::new(),
+                })
+                .collect();
+            let roots: Vec<&str> = matches.iter().flatten().map(String::as_str).collect();
+            let mut sizes = folder_sizes(&roots).into_iter();
+
+            for ((t, dir), matches) in active.iter().zip(&dirs).zip(&matches) {
+                let match_sizes: Vec<u64> =
+                    matches.iter().map(|_| sizes.next().unwrap_or(0)).collect();
+                let size = match_sizes.iter().sum();
+                total += size;
+
+                let size_dir = dir.as_ref().and_then(|dir| dir.as_deref().ok());
+                let is_glob = size_dir.is_some_and(is_pattern);
+
+                let error = match dir {
+                    Some(Err(err)) => Some(err.to_string()),
+                    _ => None,
+                };
 
-            for i in 0..rule_count {
-                let t = &rules[i];
-                if !is_rule_active(t) {
+                if !report.is_text() {
+                    let mut record = Record::new("rule")
+                        .str("name", &t.name)
+                        .bool("active", true)
+                        .opt_str("size_dir", size_dir)
+                        .u64("size_bytes", size);
+                    if let Some(error) = &error {
+                        record = record.str("error", error);
+                    }
+                    report.record(record);
+
+                    if is_glob {
+                        for (path, size) in matches.iter().zip(&match_sizes) {
+                            report.record(
+                                Record::new("match")
+                                    .str("name", &t.name)
+                                    .str("path", path)
+                                    .u64("size_bytes", *size),
+                            );
+                        }
+                    }
                     continue;
                 }
 
-                let size = match &t.size_dir {
-                    Some(dir) => {
-                        let s = folder_size(expand_path(*dir).as_str());
-                        total += s;
-                        s
-                    }
-                    None => 0,
-                };
+                if let Some(error) = &error {
+                    let mut line = ConstStr::<512>::from("  <#555555>");
+                    push_escaped(&mut line, &t.name);
+                    line.push_str(": <red>");
+                    push_escaped(&mut line, error);
+                    print_styled(line.as_str());
+                    continue;
+                }
 
                 let mut line = ConstStr::<256>::new();
                 line.push_str("  ");
@@ -141,100 +339,41 @@ fn main() {
                     line.push_str("<#55AAFF>");
                 }
 
-                line.push_str(t.name.as_str());
+                push_escaped(&mut line, &t.name);
                 line.push_str(": <white>");
                 line.push_str(human_size(size).as_str());
 
                 print_styled(line.as_str());
+
+                if is_glob {
+                    for (path, size) in matches.iter().zip(&match_sizes) {
+                        let mut line = ConstStr::<512>::from("    <#555555>");
+                        push_escaped(&mut line, path);
+                        line.push_str(": <white>");
+                        line.push_str(human_size(*size).as_str());
+                        print_styled(line.as_str());
+                    }
+                }
             }
 
             let mut final_msg = ConstStr::<128>::new();
             final_msg.push_str("\n<#55AAFF>Total: <white>");
             final_msg.push_str(human_size(total).as_str());
-            print_styled(final_msg.as_str());
+            report.text(final_msg.as_str());
+
+            report.record(Record::new("total").u64("size_bytes", total));
+            report.finish();
         }
         Commands::Clean => {
-            let start = Timer::now();
-            let mut total: u64 = 0;
-
-            let mut msg = ConstStr::<256>::new();
-            let mut count = 0;
-
-            for tool in rules {
-                if !is_rule_active(&tool) {
-                    continue;
-                }
-
-                count += 1;
-
-                msg.clear();
-                msg.push_str("<yellow>Cleaning cache of ");
-                msg.push_str(&tool.name);
-                msg.push_str("...");
-                print_styled(&msg);
-
-                match &tool.size_dir {
-                    Some(k) => {
-                        let size = folder_size(&expand_path(*k));
-                        total += size;
-                    }
-                    None => {}
-                }
-
-                let clean = if is_unsafe && tool.size_dir.is_some() {
-                    CleanMethod::CleanPath(tool.size_dir.unwrap())
-                } else {
-                    tool.clean.clone()
-                };
-
-                match &clean {
-                    CleanMethod::RunCommand(cmd, args) => {
-                        msg.clear();
-                        msg.push_str("<#D4D4D4>Running command: <gray>");
-                        msg.push_str(cmd);
-                        msg.push_u8(b' ');
-                        msg.push_str(args);
-                        print_styled(&msg);
-
-                        if let Err(code) = crate::r#abstract::run_command(cmd, args) {
-                            msg.push_u64(code as u64);
-                            msg.push_u8(b')');
-                            print_styled(&msg);
-                        }
-                    }
-                    CleanMethod::CleanPath(path) => {
-                        let dir = expand_path(*path);
-
-                        let mut escaped_dir = ConstStr::<512>::new();
-                        for b in dir.as_str().bytes() {
-                            if b == b'\\' {
-                                escaped_dir.push_str("\\\\");
-                            } else {
-                                escaped_dir.push_u8(b);
-                            }
-                        }
-
-                        msg.clear();
-                        msg.push_str("<#D4D4D4>Clearing directory: <gray>\"");
-                        msg.push_str(&escaped_dir);
-                        msg.push_str("\"");
-                        print_styled(&msg);
-
-                        let _ = clear_dir(&dir);
-                    }
-                }
-            }
-
-            msg.clear();
-            msg.push_str("<green>Done! Cleaned ");
-            msg.push_u64(count);
-            msg.push_str(" caches <dark_green>(");
-            msg.push_str(&human_size(total));
-            msg.push_str(")</dark_green>. Took ");
-            msg.push_u64(start.elapsed_ms());
-            msg.push_str(" ms.");
-            print_styled(&msg);
+            clean::run(&rules, &options, &mut report);
+        }
+        Commands::Restore => {
+            trash::run_restore(&options, &mut report);
+        }
+        Commands::Trash(trash_command) => {
+            trash::run_trash(trash_command, &options, &mut report);
         }
+        Commands::Check | Commands::Rules(_) => {}
     }
 
     exit(0); // i got no idea why it doesnt exit normally
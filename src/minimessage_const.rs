@@ -61,6 +61,8 @@ impl<const N: usize> ConstStr<N> {
             str::from_utf8_unchecked(core::slice::from_raw_parts(self.buffer.as_ptr(), self.len))
         }
     }
+    /// Appends as much of `s` as fits, the rest is dropped. Use `try_push_str` wherever
+    /// losing bytes would change the meaning, like paths.
     pub const fn push_str(&mut self, s: &str) {
         let bytes = s.as_bytes();
         let mut i = 0;
@@ -71,6 +73,18 @@ impl<const N: usize> ConstStr<N> {
         }
     }
 
+    /// Appends `s` only if all of it fits. Otherwise the string is left as is and the
+    /// number of bytes that did not fit is returned.
+    pub const fn try_push_str(&mut self, s: &str) -> Result<(), usize> {
+        let len = self.len + s.len();
+        if len > N {
+            return Err(len - N);
+        }
+
+        self.push_str(s);
+        Ok(())
+    }
+
     pub const fn push_u8n(&mut self, mut n: u8) {
         if n >= 100 {
             self.push_u8(b'0' + n / 100);
@@ -540,11 +554,7 @@ pub const fn serialize<const CAP: usize>(input: &str) -> ConstStr<CAP> {
 
             if end < bytes.len() {
                 let content = substr(input, start, end);
-                let is_close = if !content.is_empty() && content.as_bytes()[0] == b'/' {
-                    true
-                } else {
-                    false
-                };
+                let is_close = !content.is_empty() && content.as_bytes()[0] == b'/';
 
                 if is_close {
                     // Pop
@@ -673,7 +683,7 @@ pub const fn serialize<const CAP: usize>(input: &str) -> ConstStr<CAP> {
                 Fill::Solid(c) => fg = Some(c),
                 Fill::Gradient { start, end, phase } => {
                     if grad_active {
-                        let t = (grad_char_count as i32 * SCALE) / grad_total_len as i32;
+                        let t = (grad_char_count * SCALE) / grad_total_len;
                         let t_phased = (t + phase) % SCALE;
                         fg = Some(Color::new(
                             lerp_u8(start.r, end.r, t_phased),
@@ -684,7 +694,7 @@ pub const fn serialize<const CAP: usize>(input: &str) -> ConstStr<CAP> {
                 }
                 Fill::Rainbow { phase, reverse } => {
                     if grad_active {
-                        let step = (grad_char_count as i32 * SCALE) / grad_total_len as i32;
+                        let step = (grad_char_count * SCALE) / grad_total_len;
                         let mut hue = step + phase;
                         if reverse {
                             hue = SCALE - hue;
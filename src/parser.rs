@@ -0,0 +1,988 @@
+use crate::{
+    cli::find_suggestion,
+    files::{check_pattern, expand_vars, parse_duration, parse_size},
+    regex::Regex,
+    rules::{CacheRule, CleanMethod, CleanStep, DetectMethod, OnError, SizeDir, ValueMatch},
+};
+use std::fmt;
+
+/// The newest rules format this build understands.
+pub const FORMAT_VERSION: u32 = 1;
+
+const KEYS: [&str; 6] = ["name", "detect", "clean", "on-error", "size", "enabled"];
+const DETECT_KINDS: [&str; 5] = ["binary", "env", "path", "query", "command"];
+/// How long a `command` probe may run unless it sets `timeout`.
+const PROBE_TIMEOUT_SECS: u64 = 5;
+/// Detection methods that combine others, written like `all(binary:docker, env:CI)`.
+const COMBINATORS: [&str; 3] = ["all", "any", "not"];
+const CLEAN_KINDS: [&str; 4] = ["command", "path", "older-than", "budget"];
+
+pub struct ParseError {
+    pub file: String,
+    /// 1-based, like an editor shows them.
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+/// A rule along with the line its definition starts on.
+pub struct ParsedRule {
+    pub line: usize,
+    pub rule: CacheRule,
+}
+
+/// Everything read from one rules file.
+pub struct RulesFile {
+    pub rules: Vec<ParsedRule>,
+    /// Names of rules from earlier layers that this file turns off with `enabled = false`.
+    pub disabled: Vec<String>,
+    /// A file with errors must not be used.
+    pub errors: Vec<ParseError>,
+}
+
+/// Collects errors so one pass reports every problem in a file, not just the first.
+struct Errors<'a> {
+    file: &'a str,
+    list: Vec<ParseError>,
+}
+
+impl Errors<'_> {
+    fn push(&mut self, line: usize, column: usize, message: impl Into<String>) {
+        self.list.push(ParseError {
+            file: self.file.to_string(),
+            line,
+            column,
+            message: message.into(),
+        });
+    }
+
+    /// Unwraps a value, recording its error at the 0-based `offset` into `line` instead.
+    fn take<T>(&mut self, line: usize, result: Result<T, (usize, String)>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err((offset, message)) => {
+                self.push(line, offset + 1, message);
+                None
+            }
+        }
+    }
+}
+
+/// Parses a rules file. Files starting with a `version` line use the keyed format, anything
+/// else is read as the original four-lines-per-rule format.
+pub fn parse_rules(file: &str, bytes: &[u8]) -> RulesFile {
+    let mut errors = Errors {
+        file,
+        list: Vec::new(),
+    };
+
+    let content = match std::str::from_utf8(bytes) {
+        Ok(content) => content,
+        Err(err) => {
+            let valid = &bytes[..err.valid_up_to()];
+            let line = valid.iter().filter(|&&b| b == b'\n').count() + 1;
+            let column = valid.iter().rev().take_while(|&&b| b != b'\n').count() + 1;
+            errors.push(line, column, "file is not valid UTF-8");
+            return RulesFile {
+                rules: Vec::new(),
+                disabled: Vec::new(),
+                errors: errors.list,
+            };
+        }
+    };
+
+    let is_keyed = content
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .is_some_and(|l| l.starts_with("version"));
+
+    let mut disabled = Vec::new();
+    let rules = if is_keyed {
+        parse_keyed(content, &mut errors, &mut disabled)
+    } else {
+        parse_legacy(content, &mut errors)
+    };
+
+    RulesFile {
+        rules,
+        disabled,
+        errors: errors.list,
+    }
+}
+
+/// A `[rule]` block as it is being read, with the position of every key for errors.
+struct Draft {
+    line: usize,
+    name: Option<String>,
+    detect: Option<DetectMethod>,
+    clean: Vec<CleanStep>,
+    size: Option<SizeDir>,
+    enabled: bool,
+    /// Whether the last `clean` step already has an `on-error`.
+    policy_set: bool,
+    seen: Vec<&'static str>,
+}
+
+impl Draft {
+    fn new(line: usize) -> Self {
+        Self {
+            line,
+            name: None,
+            detect: None,
+            clean: Vec::new(),
+            size: None,
+            enabled: true,
+            policy_set: false,
+            seen: Vec::new(),
+        }
+    }
+
+    fn finish(self, errors: &mut Errors, out: &mut Vec<ParsedRule>, disabled: &mut Vec<String>) {
+        // Turning a rule off only needs its name.
+        if !self.enabled {
+            match self.name {
+                Some(name) => disabled.push(name),
+                None if !self.seen.contains(&"name") => {
+                    errors.push(self.line, 1, "rule is missing `name`")
+                }
+                None => {}
+            }
+            return;
+        }
+
+        let mut missing = Vec::new();
+        if self.name.is_none() && !self.seen.contains(&"name") {
+            missing.push("name");
+        }
+        if self.detect.is_none() && !self.seen.contains(&"detect") {
+            missing.push("detect");
+        }
+        if self.clean.is_empty() && !self.seen.contains(&"clean") {
+            missing.push("clean");
+        }
+
+        if !missing.is_empty() {
+            errors.push(
+                self.line,
+                1,
+                format!("rule is missing `{}`", missing.join("`, `")),
+            );
+            return;
+        }
+
+        // Keys that were present but invalid already reported an error.
+        if let (Some(name), Some(detect), false) = (self.name, self.detect, self.clean.is_empty()) {
+            out.push(ParsedRule {
+                line: self.line,
+                rule: CacheRule {
+                    name,
+                    detect,
+                    clean: self.clean,
+                    size_dir: self.size,
+                },
+            });
+        }
+    }
+}
+
+fn parse_keyed(content: &str, errors: &mut Errors, disabled: &mut Vec<String>) -> Vec<ParsedRule> {
+    let mut rules = Vec::new();
+    let mut draft: Option<Draft> = None;
+    let mut has_version = false;
+
+    for (index, raw) in content.lines().enumerate() {
+        let line_no = index + 1;
+        let line = raw.trim_end();
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim_start();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            if line == "[rule]" {
+                if let Some(done) = draft.take() {
+                    done.finish(errors, &mut rules, disabled);
+                }
+                draft = Some(Draft::new(line_no));
+            } else {
+                errors.push(
+                    line_no,
+                    indent + 1,
+                    format!("unknown section `{}`, expected `[rule]`", line),
+                );
+            }
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            errors.push(line_no, indent + 1, "expected `key = value`");
+            continue;
+        };
+
+        let key = key.trim();
+        let after = &line[line.len() - value.len()..];
+        let value = value.trim();
+        // 1-based column of the first character of the value.
+        let value_column = indent + line.len() - after.trim_start().len() + 1;
+
+        if key == "version" {
+            if has_version || draft.is_some() || !rules.is_empty() {
+                errors.push(line_no, indent + 1, "`version` must be the first line");
+            } else if value.parse::<u32>().ok() != Some(FORMAT_VERSION) {
+                errors.push(
+                    line_no,
+                    value_column,
+                    format!(
+                        "unsupported version `{}`, this build reads version {}",
+                        value, FORMAT_VERSION
+                    ),
+                );
+            }
+            has_version = true;
+            continue;
+        }
+
+        let Some(current) = draft.as_mut() else {
+            errors.push(
+                line_no,
+                indent + 1,
+                format!("`{}` outside of a `[rule]` block", key),
+            );
+            continue;
+        };
+
+        let Some(&known) = KEYS.iter().find(|&&k| k == key) else {
+            let mut message = format!("unknown key `{}`", key);
+            if let Some(suggestion) = find_suggestion(key, KEYS) {
+                message.push_str(&format!(", did you mean `{}`?", suggestion));
+            }
+            errors.push(line_no, indent + 1, message);
+            continue;
+        };
+
+        // A rule runs every `clean` step in order, each may be followed by its `on-error`.
+        let (repeated, scope) = match known {
+            "clean" => (false, "rule"),
+            "on-error" if !current.seen.contains(&"clean") => {
+                errors.push(line_no, indent + 1, "`on-error` must follow a `clean` step");
+                continue;
+            }
+            "on-error" => (current.policy_set, "step"),
+            _ => (current.seen.contains(&known), "rule"),
+        };
+        if repeated {
+            errors.push(
+                line_no,
+                indent + 1,
+                format!("`{}` is already set for this {}", known, scope),
+            );
+            continue;
+        }
+        current.seen.push(known);
+
+        if value.is_empty() {
+            errors.push(line_no, value_column, format!("`{}` has no value", known));
+            continue;
+        }
+
+        let result = match known {
+            "name" => {
+                current.name = Some(value.to_string());
+                Ok(())
+            }
+            "detect" => parse_detect(value).map(|d| current.detect = Some(d)),
+            "clean" => {
+                current.policy_set = false;
+                parse_clean(value).map(|c| current.clean.push(CleanStep::new(c)))
+            }
+            "on-error" => {
+                current.policy_set = true;
+                let on_error = match value {
+                    "stop" => Ok(OnError::Stop),
+                    "continue" => Ok(OnError::Continue),
+                    _ => Err((
+                        0,
+                        format!("expected `stop` or `continue`, found `{}`", value),
+                    )),
+                };
+                on_error.map(|on_error| {
+                    if let Some(step) = current.clean.last_mut() {
+                        step.on_error = on_error;
+                    }
+                })
+            }
+            "enabled" => match value {
+                "true" => Ok(()),
+                "false" => {
+                    current.enabled = false;
+                    Ok(())
+                }
+                _ => Err((0, format!("expected `true` or `false`, found `{}`", value))),
+            },
+            _ => parse_size_dir(value).map(|s| current.size = Some(s)),
+        };
+
+        if let Err((offset, message)) = result {
+            errors.push(line_no, value_column + offset, message);
+        }
+    }
+
+    if !has_version {
+        errors.push(1, 1, "missing `version` line");
+    }
+
+    if let Some(done) = draft.take() {
+        done.finish(errors, &mut rules, disabled);
+    }
+
+    rules
+}
+
+/// Splits `kind:argument`. Errors carry the column offset into the value.
+fn split_kind<'a>(
+    value: &'a str,
+    kinds: &[&'static str],
+) -> Result<(&'a str, &'a str), (usize, String)> {
+    let Some((kind, argument)) = value.split_once(':') else {
+        return Err((
+            0,
+            format!(
+                "expected `kind:argument` with kind one of {}",
+                kinds.join(", ")
+            ),
+        ));
+    };
+
+    if !kinds.contains(&kind) {
+        let mut message = format!("unknown method `{}`", kind);
+        match find_suggestion(kind, kinds.iter().copied()) {
+            Some(suggestion) => message.push_str(&format!(", did you mean `{}`?", suggestion)),
+            None => message.push_str(&format!(", expected one of {}", kinds.join(", "))),
+        }
+        return Err((0, message));
+    }
+
+    if argument.trim().is_empty() {
+        return Err((kind.len() + 1, format!("`{}` needs an argument", kind)));
+    }
+
+    Ok((kind, argument.trim()))
+}
+
+fn parse_detect(value: &str) -> Result<DetectMethod, (usize, String)> {
+    if let Some((name, inner)) = split_call(value)? {
+        return parse_combinator(name, inner);
+    }
+
+    let (kind, argument) = split_kind(value, &DETECT_KINDS)?;
+
+    let offset = kind.len() + 1;
+
+    detect_method(kind, argument).map_err(|(o, message)| (offset + o, message))
+}
+
+/// Splits `name(inner)` when the value starts with a call, and `None` for a plain method.
+fn split_call(value: &str) -> Result<Option<(&str, &str)>, (usize, String)> {
+    let name_len = value
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(value.len());
+    let (name, rest) = value.split_at(name_len);
+    let Some(inner) = rest.trim_start().strip_prefix('(') else {
+        return Ok(None);
+    };
+
+    if !COMBINATORS.contains(&name) {
+        let mut message = format!("unknown condition `{}`", name);
+        match find_suggestion(name, COMBINATORS) {
+            Some(suggestion) => message.push_str(&format!(", did you mean `{}`?", suggestion)),
+            None => message.push_str(&format!(", expected one of {}", COMBINATORS.join(", "))),
+        }
+        return Err((0, message));
+    }
+
+    match inner.strip_suffix(')') {
+        Some(inner) => Ok(Some((name, inner))),
+        None => Err((value.len() - inner.len() - 1, "unclosed `(`".to_string())),
+    }
+}
+
+/// Parses the comma separated conditions of `all`, `any` or `not`.
+fn parse_combinator(name: &str, inner: &str) -> Result<DetectMethod, (usize, String)> {
+    // Offsets are relative to `inner`, which starts right after `name(`.
+    let start = name.len() + 1;
+    let mut conditions = Vec::new();
+
+    for (offset, part) in split_top_level(inner, ',').map_err(|(o, m)| (start + o, m))? {
+        let offset = start + offset + part.len() - part.trim_start().len();
+        if part.trim().is_empty() {
+            return Err((offset, format!("expected a condition in `{}`", name)));
+        }
+        let condition = parse_detect(part.trim()).map_err(|(o, m)| (offset + o, m))?;
+        conditions.push(condition);
+    }
+
+    match name {
+        "not" if conditions.len() != 1 => Err((0, "`not` takes exactly one condition".to_string())),
+        "not" => Ok(DetectMethod::Not(Box::new(conditions.remove(0)))),
+        "all" => Ok(DetectMethod::All(conditions)),
+        _ => Ok(DetectMethod::Any(conditions)),
+    }
+}
+
+/// Splits on every `separator` that is not nested in parentheses or quotes, with the
+/// offset of every part.
+fn split_top_level(inner: &str, separator: char) -> Result<Vec<(usize, &str)>, (usize, String)> {
+    let mut parts = Vec::new();
+    let mut depth: usize = 0;
+    let mut quote: Option<char> = None;
+    let mut start = 0;
+
+    for (i, c) in inner.char_indices() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => return Err((i, "unexpected `)`".to_string())),
+            (None, ')') => depth -= 1,
+            (None, c) if c == separator && depth == 0 => {
+                parts.push((start, &inner[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if depth > 0 {
+        return Err((inner.len(), "unclosed `(`".to_string()));
+    }
+    parts.push((start, &inner[start..]));
+
+    Ok(parts)
+}
+
+fn detect_method(kind: &str, argument: &str) -> Result<DetectMethod, (usize, String)> {
+    Ok(match kind {
+        "binary" => DetectMethod::Binary(argument.to_string()),
+        "env" => parse_env(argument)?,
+        "path" => DetectMethod::PathExists(path_arg(argument)?),
+        "command" => parse_probe(argument)?,
+        _ => {
+            let (exe, args) = split_command(argument)?;
+            DetectMethod::Query(exe, args)
+        }
+    })
+}
+
+/// Splits a shell-style command line into arguments. Single quotes keep everything
+/// literally, double quotes keep all but `\"`, and outside quotes a backslash only
+/// escapes a quote or whitespace so Windows paths need no doubling.
+pub fn split_args(line: &str) -> Result<Vec<String>, (usize, String)> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quote: Option<(char, usize)> = None;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let escapes = |next: char| match quote {
+            None => next == '"' || next == '\'' || next.is_whitespace(),
+            Some(('"', _)) => next == '"',
+            Some(_) => false,
+        };
+
+        match (quote, c) {
+            (Some((open, _)), c) if c == open => quote = None,
+            (_, '\\') if chars.peek().is_some_and(|&(_, next)| escapes(next)) => {
+                let (_, next) = chars.next().unwrap();
+                current.get_or_insert_with(String::new).push(next);
+            }
+            (None, '"' | '\'') => {
+                quote = Some((c, i));
+                current.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => args.extend(current.take()),
+            (_, c) => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if let Some((open, i)) = quote {
+        return Err((i, format!("unclosed `{}`", open)));
+    }
+    args.extend(current);
+
+    Ok(args)
+}
+
+/// Joins arguments back into a line `split_args` reads the same, for showing commands.
+pub fn join_args(args: &[String]) -> String {
+    let quoted: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            if !arg.is_empty()
+                && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+                && !arg.ends_with('\\')
+            {
+                arg.clone()
+            } else {
+                // A trailing backslash would escape the closing quote, or the space after
+                // the argument, so those go in single quotes of their own.
+                let body = arg.trim_end_matches('\\');
+                let mut quoted = format!("\"{}\"", body.replace('"', "\\\""));
+                if body.len() < arg.len() {
+                    quoted.push('\'');
+                    quoted.push_str(&arg[body.len()..]);
+                    quoted.push('\'');
+                }
+                quoted
+            }
+        })
+        .collect();
+
+    quoted.join(" ")
+}
+
+/// `NAME`, `NAME=VALUE`, `NAME^=PREFIX` or `NAME~=REGEX`, optionally followed by
+/// `;nonempty` to treat an empty value as unset.
+fn parse_env(argument: &str) -> Result<DetectMethod, (usize, String)> {
+    let (spec, empty_is_unset) = match argument.strip_suffix(";nonempty") {
+        Some(spec) => (spec, true),
+        None => (argument, false),
+    };
+
+    let (name, value) = split_match(spec)?;
+
+    if name.is_empty() {
+        return Err((0, "expected a variable name".to_string()));
+    }
+    if let Some(i) = name.find(';') {
+        return Err((
+            i + 1,
+            format!("unknown option `{}`, expected `nonempty`", &name[i + 1..]),
+        ));
+    }
+
+    Ok(DetectMethod::EnvVar {
+        name: name.to_string(),
+        value,
+        empty_is_unset,
+    })
+}
+
+/// Splits `NAME`, `NAME=VALUE`, `NAME^=PREFIX` or `NAME~=REGEX` into the name and how
+/// the value must look.
+fn split_match(spec: &str) -> Result<(&str, ValueMatch), (usize, String)> {
+    let Some(i) = spec.find('=') else {
+        return Ok((spec, ValueMatch::Any));
+    };
+
+    let (name, operator) = match spec[..i].chars().last() {
+        Some(c @ ('^' | '~')) => (&spec[..i - 1], Some(c)),
+        _ => (&spec[..i], None),
+    };
+    let expected = spec[i + 1..].to_string();
+
+    let value = match operator {
+        Some('^') => ValueMatch::Prefix(expected),
+        Some(_) => {
+            ValueMatch::Regex(Regex::new(&expected).map_err(|(o, message)| (i + 1 + o, message))?)
+        }
+        None => ValueMatch::Exact(expected),
+    };
+
+    Ok((name, value))
+}
+
+/// `EXE ARGS`, followed by `;`-separated `timeout=DURATION`, `exit=CODE` or `exit=any`,
+/// and a `stdout` match like the ones of `env`.
+fn parse_probe(argument: &str) -> Result<DetectMethod, (usize, String)> {
+    let mut parts = split_top_level(argument, ';')?.into_iter();
+    let (_, command) = parts.next().unwrap_or_default();
+    let (exe, args) = split_command(command.trim_end())?;
+
+    let mut timeout = PROBE_TIMEOUT_SECS;
+    let mut exit = Some(0);
+    let mut stdout = ValueMatch::Any;
+
+    for (offset, option) in parts {
+        let offset = offset + option.len() - option.trim_start().len();
+        let option = option.trim();
+        let (key, value) = split_match(option).map_err(|(o, m)| (offset + o, m))?;
+        // Where the value starts, after `=`, `^=` or `~=`.
+        let value_offset = offset + option.find('=').map_or(option.len(), |i| i + 1);
+
+        match (key, value) {
+            ("timeout", ValueMatch::Exact(value)) => match parse_duration(&value) {
+                // The probe would be killed before it could answer.
+                Some(duration) if duration.is_zero() => {
+                    return Err((value_offset, "timeout must be longer than 0s".to_string()))
+                }
+                Some(duration) => timeout = duration.as_secs(),
+                None => {
+                    return Err((
+                        value_offset,
+                        format!("invalid timeout `{}`, expected e.g. 5s", value),
+                    ))
+                }
+            },
+            ("exit", ValueMatch::Exact(value)) if value == "any" => exit = None,
+            ("exit", ValueMatch::Exact(value)) => match value.parse() {
+                Ok(code) => exit = Some(code),
+                Err(_) => {
+                    return Err((
+                        value_offset,
+                        format!("invalid exit code `{}`, expected a number or `any`", value),
+                    ))
+                }
+            },
+            (
+                "stdout",
+                value @ (ValueMatch::Exact(_) | ValueMatch::Prefix(_) | ValueMatch::Regex(_)),
+            ) => stdout = value,
+            _ => {
+                return Err((
+                    offset,
+                    format!(
+                        "unknown option `{}`, expected `timeout=`, `exit=` or `stdout=`",
+                        option
+                    ),
+                ))
+            }
+        }
+    }
+
+    Ok(DetectMethod::Command {
+        exe,
+        args,
+        timeout,
+        exit,
+        stdout,
+    })
+}
+
+/// Splits `EXE ARGS` into the executable and its arguments.
+fn split_command(argument: &str) -> Result<(String, Vec<String>), (usize, String)> {
+    let mut args = split_args(argument)?.into_iter();
+
+    match args.next() {
+        Some(exe) if !exe.is_empty() => Ok((exe, args.collect())),
+        _ => Err((0, "expected an executable".to_string())),
+    }
+}
+
+/// A `size` value, either a path or `query:` followed by the command that prints it.
+fn parse_size_dir(value: &str) -> Result<SizeDir, (usize, String)> {
+    match value.strip_prefix("query:") {
+        Some(command) if command.trim().is_empty() => {
+            Err((value.len(), "`query` needs an argument".to_string()))
+        }
+        Some(command) => {
+            let offset = value.len() - command.trim_start().len();
+            let (exe, args) =
+                split_command(command.trim()).map_err(|(o, message)| (offset + o, message))?;
+            Ok(SizeDir::Query(exe, args))
+        }
+        None => path_arg(value).map(SizeDir::Path),
+    }
+}
+
+/// Checks the variable and glob syntax of a path now, whether the variables are set is
+/// only known when it is used.
+fn path_arg(path: &str) -> Result<String, (usize, String)> {
+    expand_vars(path, &|_| Some(String::new()))?;
+    check_pattern(path)?;
+    Ok(path.to_string())
+}
+
+fn parse_clean(value: &str) -> Result<CleanMethod, (usize, String)> {
+    let (kind, argument) = split_kind(value, &CLEAN_KINDS)?;
+    let offset = kind.len() + 1;
+
+    let method = if kind == "command" {
+        split_command(argument).map(|(exe, args)| CleanMethod::RunCommand(exe, args))
+    } else {
+        clean_method(kind, argument)
+    };
+
+    method.map_err(|(o, message)| (offset + o, message))
+}
+
+/// The path based cleaning methods, offsets are relative to `argument`.
+fn clean_method(kind: &str, argument: &str) -> Result<CleanMethod, (usize, String)> {
+    match kind {
+        "path" => Ok(CleanMethod::CleanPath(path_arg(argument)?)),
+        "older-than" => {
+            let (path, age) = split_param(argument, "PATH;AGE")?;
+            match parse_duration(age) {
+                Some(age) => Ok(CleanMethod::CleanOlderThan(path_arg(path)?, age.as_secs())),
+                None => Err((
+                    path.len() + 1,
+                    format!("invalid age `{}`, expected e.g. 12h or 30d", age),
+                )),
+            }
+        }
+        _ => {
+            let (path, budget) = split_param(argument, "PATH;SIZE")?;
+            match parse_size(budget) {
+                Some(budget) => Ok(CleanMethod::CleanToBudget(path_arg(path)?, budget)),
+                None => Err((
+                    path.len() + 1,
+                    format!("invalid size `{}`, expected e.g. 500MB or 5GB", budget),
+                )),
+            }
+        }
+    }
+}
+
+/// Splits `PATH;PARAM` on the last semicolon, paths may contain their own.
+fn split_param<'a>(
+    argument: &'a str,
+    expected: &str,
+) -> Result<(&'a str, &'a str), (usize, String)> {
+    match argument.rsplit_once(';') {
+        Some((path, param)) if !path.is_empty() && !param.is_empty() => Ok((path, param.trim())),
+        _ => Err((0, format!("expected `{}`", expected))),
+    }
+}
+
+/// The original format: a name line followed by detection, cleaning and size lines, where
+/// the first byte of the method lines is the method ID. Blank lines are ignored.
+fn parse_legacy(content: &str, errors: &mut Errors) -> Vec<ParsedRule> {
+    let mut rules = Vec::new();
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim_end()))
+        .filter(|(_, l)| !l.trim().is_empty());
+
+    while let Some((name_line, name)) = lines.next() {
+        let (Some(detect), Some(clean), Some(size)) = (lines.next(), lines.next(), lines.next())
+        else {
+            errors.push(
+                name_line,
+                1,
+                format!(
+                    "rule `{}` is incomplete, expected detection, cleaning and size lines",
+                    name
+                ),
+            );
+            break;
+        };
+
+        // Parameters start right after the one byte ID, hence the extra column.
+        let detect = match split_id(detect) {
+            Some((id @ '1'..='3', arg)) => {
+                let kind = DETECT_KINDS[(id as u8 - b'1') as usize];
+                errors.take(
+                    detect.0,
+                    detect_method(kind, arg).map_err(|(o, m)| (o + 1, m)),
+                )
+            }
+            _ => {
+                errors.push(
+                    detect.0,
+                    1,
+                    "expected a detection method ID (1-3) followed by its parameters",
+                );
+                None
+            }
+        };
+
+        let clean = match split_id(clean) {
+            Some(('1', arg)) => {
+                let (exe, args) = arg.split_once(';').unwrap_or((arg, ""));
+                errors.take(
+                    clean.0,
+                    split_args(args)
+                        .map(|args| CleanMethod::RunCommand(exe.to_string(), args))
+                        .map_err(|(o, m)| (o + exe.len() + 2, m)),
+                )
+            }
+            Some((id @ '2'..='4', arg)) => {
+                let kind = CLEAN_KINDS[(id as u8 - b'1') as usize];
+                errors.take(
+                    clean.0,
+                    clean_method(kind, arg).map_err(|(o, m)| (o + 1, m)),
+                )
+            }
+            _ => {
+                errors.push(
+                    clean.0,
+                    1,
+                    "expected a cleaning method ID (1-4) followed by its parameters",
+                );
+                None
+            }
+        };
+
+        let size_dir = errors.take(size.0, path_arg(size.1.trim()));
+
+        if let (Some(detect), Some(clean), Some(size_dir)) = (detect, clean, size_dir) {
+            rules.push(ParsedRule {
+                line: name_line,
+                rule: CacheRule {
+                    name: name.trim().to_string(),
+                    detect,
+                    clean: vec![CleanStep::new(clean)],
+                    size_dir: Some(SizeDir::Path(size_dir)),
+                },
+            });
+        }
+    }
+
+    rules
+}
+
+/// Splits a legacy method line into its ID and a non-empty parameter.
+fn split_id((_, line): (usize, &str)) -> Option<(char, &str)> {
+    let mut chars = line.trim_start().chars();
+    let id = chars.next()?;
+    let param = chars.as_str();
+
+    if param.is_empty() {
+        None
+    } else {
+        Some((id, param))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_value_offsets() {
+        let error = |argument: &str| parse_probe(argument).err().unwrap().0;
+        assert_eq!(error("tool; timeout=x"), 14);
+        assert_eq!(error("tool; exit=x"), 11);
+        assert_eq!(error("tool;stdout^=x;timeout=1x"), 23);
+        assert_eq!(error("tool; stdout~=a["), 15);
+        assert_eq!(error("tool; timeout=0s"), 14);
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn split_args_quotes() {
+        assert_eq!(split_args("a  b\tc").unwrap(), args(&["a", "b", "c"]));
+        assert_eq!(
+            split_args(r#"a "b c" 'd e'"#).unwrap(),
+            args(&["a", "b c", "d e"])
+        );
+        assert_eq!(split_args(r#""" ''"#).unwrap(), args(&["", ""]));
+        assert_eq!(split_args(r#"a"b c"d"#).unwrap(), args(&["ab cd"]));
+        assert_eq!(split_args(r#"'a\"b'"#).unwrap(), args(&[r#"a\"b"#]));
+        assert_eq!(split_args(r#""a\"b""#).unwrap(), args(&[r#"a"b"#]));
+    }
+
+    #[test]
+    fn split_args_backslashes() {
+        assert_eq!(
+            split_args(r"C:\Program\ Files\x").unwrap(),
+            args(&[r"C:\Program Files\x"])
+        );
+        assert_eq!(split_args(r#"\"a\""#).unwrap(), args(&[r#""a""#]));
+        assert_eq!(split_args(r"a\\b c\").unwrap(), args(&[r"a\\b", r"c\"]));
+        assert_eq!(split_args(r#""C:\dir\""#).unwrap_err().0, 0);
+        assert_eq!(split_args("a 'b").unwrap_err().0, 2);
+    }
+
+    #[test]
+    fn join_args_round_trips() {
+        let cases = [
+            args(&["go", "env", "GOCACHE"]),
+            args(&["a b", "", "c"]),
+            args(&[r#"say "hi""#, "it's"]),
+            args(&[r"C:\Program Files\x", r"C:\dir\"]),
+            args(&[r"with space\", r"both ' and \"]),
+            args(&[r#"a\"b"#, r"\\", r#"\""#]),
+        ];
+
+        for case in cases {
+            let line = join_args(&case);
+            assert_eq!(split_args(&line).unwrap(), case, "{}", line);
+        }
+    }
+
+    #[test]
+    fn combinators_nest() {
+        let detect = parse_detect("all(binary:a, any(env:X, not(path:/tmp)))")
+            .ok()
+            .unwrap();
+        let DetectMethod::All(all) = detect else {
+            panic!("expected all");
+        };
+        assert!(matches!(&all[0], DetectMethod::Binary(name) if name == "a"));
+
+        let DetectMethod::Any(any) = &all[1] else {
+            panic!("expected any");
+        };
+        assert!(matches!(&any[0], DetectMethod::EnvVar { name, .. } if name == "X"));
+        assert!(matches!(&any[1], DetectMethod::Not(inner)
+            if matches!(**inner, DetectMethod::PathExists(ref path) if path == "/tmp")));
+    }
+
+    #[test]
+    fn combinators_keep_nested_commas() {
+        let detect = parse_detect(r#"any(command:sh -c "a, b", binary:x)"#)
+            .ok()
+            .unwrap();
+        let DetectMethod::Any(any) = detect else {
+            panic!("expected any");
+        };
+        assert_eq!(any.len(), 2);
+        assert!(matches!(&any[0], DetectMethod::Command { args, .. } if args[1] == "a, b"));
+
+        let detect = parse_detect("all(any(binary:a, binary:b), env:X~=^(a,b)$)")
+            .ok()
+            .unwrap();
+        let DetectMethod::All(all) = detect else {
+            panic!("expected all");
+        };
+        assert_eq!(all.len(), 2);
+        assert!(matches!(&all[0], DetectMethod::Any(any) if any.len() == 2));
+    }
+
+    #[test]
+    fn combinator_counts() {
+        let error = |value: &str| parse_detect(value).err().unwrap();
+        assert_eq!(
+            error("not()"),
+            (4, "expected a condition in `not`".to_string())
+        );
+        assert_eq!(
+            error("not(binary:a, binary:b)"),
+            (0, "`not` takes exactly one condition".to_string())
+        );
+        assert_eq!(
+            error("any()"),
+            (4, "expected a condition in `any`".to_string())
+        );
+        assert_eq!(error("any(binary:a,, binary:b)").0, 13);
+    }
+
+    #[test]
+    fn combinator_offsets() {
+        let error = |value: &str| parse_detect(value).err().unwrap().0;
+        assert_eq!(error("all(binary:a, foo:x)"), 14);
+        assert_eq!(error("all(binary:a, not(env:X~=a[))"), 26);
+        assert_eq!(error("all(binary:a"), 3);
+        assert_eq!(error("not (binary:a"), 4);
+        assert_eq!(error("all(binary:a))"), 12);
+        assert_eq!(error("alll(binary:a)"), 0);
+    }
+}
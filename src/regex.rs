@@ -0,0 +1,469 @@
+use std::hash::{Hash, Hasher};
+
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+    Start,
+    End,
+    /// Alternatives, each a sequence of pieces.
+    Group(Vec<Vec<Piece>>),
+}
+
+struct Piece {
+    node: Node,
+    min: usize,
+    max: Option<usize>,
+}
+
+/// A step of the compiled program, jumps are indices into it.
+#[derive(Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+    Start,
+    End,
+    /// Continues at both targets.
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// A small regex for matching environment variables and probe output. Supports literals,
+/// `.`, `[...]` classes, `\d` `\w` `\s`, groups, `|`, `^`, `$` and the `*`, `+` and `?`
+/// quantifiers. Runs as a Pike VM, so matching takes linear time in the text and never
+/// recurses.
+#[derive(Clone)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+}
+
+// Two regexes are the same if they were compiled from the same pattern.
+impl PartialEq for Regex {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for Regex {}
+
+impl Hash for Regex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pattern.hash(state);
+    }
+}
+
+const DIGIT: [(char, char); 1] = [('0', '9')];
+const WORD: [(char, char); 4] = [('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: [(char, char); 3] = [(' ', ' '), ('\t', '\r'), ('\u{a0}', '\u{a0}')];
+
+impl Regex {
+    /// Compiles `pattern`, errors carry the byte offset of the mistake.
+    pub fn new(pattern: &str) -> Result<Self, (usize, String)> {
+        let mut parser = Parser {
+            chars: pattern.char_indices().collect(),
+            pos: 0,
+            len: pattern.len(),
+        };
+
+        let alternatives = parser.alternatives(0)?;
+        let mut program = Vec::new();
+        compile_alternatives(&alternatives, &mut program);
+        program.push(Inst::Match);
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            program,
+        })
+    }
+
+    /// Whether the pattern matches anywhere in `text`, anchor with `^` and `$` for all of it.
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+
+        for pos in 0..=text.len() {
+            // A new thread at every position makes the search unanchored.
+            if self.add_thread(&mut current, 0, &text, pos) {
+                return true;
+            }
+
+            let Some(&c) = text.get(pos) else {
+                break;
+            };
+            next.clear();
+            for &pc in &current.list {
+                let advances = match &self.program[pc] {
+                    Inst::Char(expected) => c == *expected,
+                    Inst::Any => true,
+                    Inst::Class { negated, ranges } => {
+                        ranges.iter().any(|&(low, high)| (low..=high).contains(&c)) != *negated
+                    }
+                    _ => false,
+                };
+                if advances && self.add_thread(&mut next, pc + 1, &text, pos + 1) {
+                    return true;
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        false
+    }
+
+    /// Follows the jumps and assertions from `pc` at `pos`, keeping the instructions that
+    /// wait for a character. Returns whether `Match` was reached.
+    fn add_thread(&self, threads: &mut Threads, pc: usize, text: &[char], pos: usize) -> bool {
+        let mut stack = vec![pc];
+
+        while let Some(pc) = stack.pop() {
+            // Each instruction runs at most once per position, which also ends empty loops.
+            if threads.seen[pc] {
+                continue;
+            }
+            threads.seen[pc] = true;
+
+            match self.program[pc] {
+                Inst::Match => return true,
+                Inst::Jump(target) => stack.push(target),
+                Inst::Split(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                Inst::Start if pos == 0 => stack.push(pc + 1),
+                Inst::End if pos == text.len() => stack.push(pc + 1),
+                Inst::Start | Inst::End => {}
+                _ => threads.list.push(pc),
+            }
+        }
+
+        false
+    }
+}
+
+/// The instructions waiting at one position of the text.
+struct Threads {
+    list: Vec<usize>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Self {
+            list: Vec::with_capacity(len),
+            seen: vec![false; len],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.fill(false);
+    }
+}
+
+fn compile_alternatives(alternatives: &[Vec<Piece>], program: &mut Vec<Inst>) {
+    let Some((last, rest)) = alternatives.split_last() else {
+        return;
+    };
+
+    // Every alternative but the last splits off to the next one and jumps to the end.
+    let mut jumps = Vec::new();
+    for sequence in rest {
+        let split = program.len();
+        program.push(Inst::Split(split + 1, 0));
+        compile_sequence(sequence, program);
+        jumps.push(program.len());
+        program.push(Inst::Jump(0));
+        program[split] = Inst::Split(split + 1, program.len());
+    }
+    compile_sequence(last, program);
+
+    let end = program.len();
+    for jump in jumps {
+        program[jump] = Inst::Jump(end);
+    }
+}
+
+fn compile_sequence(sequence: &[Piece], program: &mut Vec<Inst>) {
+    for piece in sequence {
+        for _ in 0..piece.min {
+            compile_node(&piece.node, program);
+        }
+
+        match piece.max {
+            None => {
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile_node(&piece.node, program);
+                program.push(Inst::Jump(split));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            Some(max) => {
+                for _ in piece.min..max {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile_node(&piece.node, program);
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+            }
+        }
+    }
+}
+
+fn compile_node(node: &Node, program: &mut Vec<Inst>) {
+    program.push(match node {
+        Node::Char(c) => Inst::Char(*c),
+        Node::Any => Inst::Any,
+        Node::Class { negated, ranges } => Inst::Class {
+            negated: *negated,
+            ranges: ranges.clone(),
+        },
+        Node::Start => Inst::Start,
+        Node::End => Inst::End,
+        Node::Group(alternatives) => return compile_alternatives(alternatives, program),
+    });
+}
+
+struct Parser {
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|&(_, c)| c)
+    }
+
+    fn offset(&self) -> usize {
+        self.chars.get(self.pos).map_or(self.len, |&(i, _)| i)
+    }
+
+    fn alternatives(&mut self, depth: usize) -> Result<Vec<Vec<Piece>>, (usize, String)> {
+        let mut alternatives = vec![self.sequence(depth)?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.sequence(depth)?);
+        }
+
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self, depth: usize) -> Result<Vec<Piece>, (usize, String)> {
+        let mut pieces = Vec::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                '|' => break,
+                ')' if depth > 0 => break,
+                ')' => return Err((self.offset(), "unexpected `)`".to_string())),
+                '*' | '+' | '?' => {
+                    return Err((self.offset(), format!("`{}` has nothing to repeat", c)))
+                }
+                _ => {}
+            }
+
+            let node = self.atom(depth)?;
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                _ => {
+                    pieces.push(Piece {
+                        node,
+                        min: 1,
+                        max: Some(1),
+                    });
+                    continue;
+                }
+            };
+            self.pos += 1;
+
+            pieces.push(Piece { node, min, max });
+        }
+
+        Ok(pieces)
+    }
+
+    fn atom(&mut self, depth: usize) -> Result<Node, (usize, String)> {
+        let start = self.offset();
+        let c = self.peek().unwrap_or_default();
+        self.pos += 1;
+
+        Ok(match c {
+            '(' => {
+                let alternatives = self.alternatives(depth + 1)?;
+                if self.peek() != Some(')') {
+                    return Err((start, "unclosed `(`".to_string()));
+                }
+                self.pos += 1;
+                Node::Group(alternatives)
+            }
+            '[' => self.class(start)?,
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '\\' => {
+                let Some(escaped) = self.peek() else {
+                    return Err((start, "trailing `\\`".to_string()));
+                };
+                self.pos += 1;
+
+                let class = |negated: bool, ranges: &[(char, char)]| Node::Class {
+                    negated,
+                    ranges: ranges.to_vec(),
+                };
+                match escaped {
+                    'd' => class(false, &DIGIT),
+                    'D' => class(true, &DIGIT),
+                    'w' => class(false, &WORD),
+                    'W' => class(true, &WORD),
+                    's' => class(false, &SPACE),
+                    'S' => class(true, &SPACE),
+                    'n' => Node::Char('\n'),
+                    't' => Node::Char('\t'),
+                    c => Node::Char(c),
+                }
+            }
+            c => Node::Char(c),
+        })
+    }
+
+    /// Reads a class after its `[`, a `]` right at the start is taken literally.
+    fn class(&mut self, start: usize) -> Result<Node, (usize, String)> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut ranges = Vec::new();
+        let mut first = true;
+
+        loop {
+            let Some(c) = self.peek() else {
+                return Err((start, "unclosed `[`".to_string()));
+            };
+            self.pos += 1;
+
+            let low = match c {
+                ']' if !first => break,
+                '\\' => {
+                    let Some(escaped) = self.peek() else {
+                        return Err((start, "unclosed `[`".to_string()));
+                    };
+                    self.pos += 1;
+
+                    let shorthand: &[(char, char)] = match escaped {
+                        'd' => &DIGIT,
+                        'w' => &WORD,
+                        's' => &SPACE,
+                        _ => &[],
+                    };
+                    if !shorthand.is_empty() {
+                        ranges.extend_from_slice(shorthand);
+                        first = false;
+                        continue;
+                    }
+                    escaped
+                }
+                c => c,
+            };
+            first = false;
+
+            // `a-z` is a range, a `-` at either end is literal.
+            let is_range = self.peek() == Some('-')
+                && self.chars.get(self.pos + 1).is_some_and(|&(_, c)| c != ']');
+            if is_range {
+                let high = self.chars[self.pos + 1].1;
+                if high < low {
+                    return Err((self.offset(), format!("invalid range `{}-{}`", low, high)));
+                }
+                self.pos += 2;
+                ranges.push((low, high));
+            } else {
+                ranges.push((low, low));
+            }
+        }
+
+        Ok(Node::Class { negated, ranges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(is_match("abc", "xxabcxx"));
+        assert!(is_match("^abc", "abcxx"));
+        assert!(!is_match("^abc", "xabc"));
+        assert!(is_match("abc$", "xxabc"));
+        assert!(!is_match("abc$", "abcx"));
+        assert!(is_match("^$", ""));
+        assert!(!is_match("^$", "a"));
+    }
+
+    #[test]
+    fn alternation_and_groups() {
+        assert!(is_match("^(foo|bar)baz$", "barbaz"));
+        assert!(!is_match("^(foo|bar)baz$", "foobar"));
+        assert!(is_match("^a|b$", "ax"));
+        assert!(is_match("^a|b$", "xb"));
+        assert!(is_match("^(ab)+$", "ababab"));
+        assert!(!is_match("^(ab)+$", "aba"));
+        assert!(is_match("^colou?r$", "color"));
+        assert!(is_match("^(a*)*$", "aaa"));
+        assert!(is_match("^()$", ""));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(is_match("^[a-c]+$", "abcba"));
+        assert!(!is_match("^[a-c]+$", "abd"));
+        assert!(is_match("^[^0-9]$", "x"));
+        assert!(!is_match("^[^0-9]$", "5"));
+        assert!(is_match("^[]a]$", "]"));
+        assert!(is_match("^[a-]$", "-"));
+        assert!(is_match(r"^\d+\.\d+$", "1.25"));
+        assert!(is_match(r"^\w+\s\S$", "a_1 x"));
+        assert!(!is_match(r"^\D$", "1"));
+        assert!(is_match("^.$", "é"));
+    }
+
+    #[test]
+    fn long_input() {
+        let text = "ab".repeat(50_000) + "c";
+        assert!(is_match("^(a|b)*c$", &text));
+        assert!(!is_match("^(a|b)*d$", &text));
+
+        // Exponential for a backtracker.
+        let text = "a".repeat(10_000) + "b";
+        assert!(!is_match("^(a|a)*$", &text));
+        assert!(!is_match("^(a*)*$", &text));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |pattern: &str| Regex::new(pattern).err().unwrap();
+        assert_eq!(error("ab(c").0, 2);
+        assert_eq!(error("a[bc").0, 1);
+        assert_eq!(error("ab)").0, 2);
+        assert_eq!(error("*a").0, 0);
+        assert_eq!(error("a\\").0, 1);
+        assert_eq!(error("[z-a]").0, 2);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::ui::print_styled;
+use std::io::Write;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Ndjson,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            "ndjson" => Some(Self::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+/// A single JSON object, built up one field at a time.
+pub struct Record {
+    buffer: String,
+}
+
+impl Record {
+    pub fn new(kind: &str) -> Self {
+        let mut record = Self {
+            buffer: String::from("{"),
+        };
+        record.key("type");
+        push_json_str(&mut record.buffer, kind);
+        record
+    }
+
+    fn key(&mut self, key: &str) {
+        if self.buffer.len() > 1 {
+            self.buffer.push(',');
+        }
+        push_json_str(&mut self.buffer, key);
+        self.buffer.push(':');
+    }
+
+    pub fn str(mut self, key: &str, value: &str) -> Self {
+        self.key(key);
+        push_json_str(&mut self.buffer, value);
+        self
+    }
+
+    pub fn opt_str(mut self, key: &str, value: Option<&str>) -> Self {
+        match value {
+            Some(value) => self.str(key, value),
+            None => {
+                self.key(key);
+                self.buffer.push_str("null");
+                self
+            }
+        }
+    }
+
+    pub fn u64(mut self, key: &str, value: u64) -> Self {
+        self.key(key);
+        self.buffer.push_str(&value.to_string());
+        self
+    }
+
+    pub fn opt_u64(mut self, key: &str, value: Option<u64>) -> Self {
+        self.key(key);
+        match value {
+            Some(value) => self.buffer.push_str(&value.to_string()),
+            None => self.buffer.push_str("null"),
+        }
+        self
+    }
+
+    pub fn opt_i64(mut self, key: &str, value: Option<i64>) -> Self {
+        self.key(key);
+        match value {
+            Some(value) => self.buffer.push_str(&value.to_string()),
+            None => self.buffer.push_str("null"),
+        }
+        self
+    }
+
+    pub fn bool(mut self, key: &str, value: bool) -> Self {
+        self.key(key);
+        self.buffer.push_str(if value { "true" } else { "false" });
+        self
+    }
+
+    fn finish(mut self) -> String {
+        self.buffer.push('}');
+        self.buffer
+    }
+}
+
+fn push_json_str(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                out.push_str("\\u00");
+                out.push(char::from_digit(c as u32 >> 4, 16).unwrap_or('0'));
+                out.push(char::from_digit(c as u32 & 0xF, 16).unwrap_or('0'));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Routes command output either to the styled terminal text or to JSON records.
+pub struct Report {
+    pub format: Format,
+    records: usize,
+}
+
+impl Report {
+    pub fn new(format: Format) -> Self {
+        Self { format, records: 0 }
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.format == Format::Text
+    }
+
+    /// Prints a styled message, but only when writing text.
+    pub fn text(&self, message: impl AsRef<str>) {
+        if self.is_text() {
+            print_styled(message);
+        }
+    }
+
+    /// Emits a record. `json` streams the elements of a single array, `ndjson` one object per line.
+    pub fn record(&mut self, record: Record) {
+        let line = record.finish();
+
+        let mut stdout = std::io::stdout();
+        match self.format {
+            Format::Text => return,
+            Format::Json => {
+                let _ = stdout.write_all(if self.records == 0 {
+                    b"[\n  "
+                } else {
+                    b",\n  "
+                });
+                let _ = stdout.write_all(line.as_bytes());
+            }
+            Format::Ndjson => {
+                let _ = stdout.write_all(line.as_bytes());
+                let _ = stdout.write_all(b"\n");
+            }
+        }
+        let _ = stdout.flush();
+
+        self.records += 1;
+    }
+
+    /// Closes the JSON array. Must be called once all records have been emitted.
+    pub fn finish(&mut self) {
+        if self.format == Format::Json {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(if self.records == 0 { b"[]\n" } else { b"\n]\n" });
+        }
+    }
+}
@@ -1,36 +1,140 @@
 use crate::{
-    config::MAX_RULES,
-    files::{expand_path, find_executable},
+    error::CleanError,
+    files::{expand_glob, expand_home, expand_path, find_executable},
     minimessage_const::ConstStr,
-    ui::print_styled,
+    parser::{join_args, parse_rules},
+    r#abstract::{probe_command, query_command},
+    regex::Regex,
+    ui::{eprint_styled, push_escaped},
 };
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum DetectMethod {
-    Binary(ConstStr<64>),
-    EnvVar(ConstStr<64>),
-    PathExists(ConstStr<260>),
+    Binary(String),
+    EnvVar {
+        name: String,
+        value: ValueMatch,
+        /// Whether a variable set to nothing counts as unset.
+        empty_is_unset: bool,
+    },
+    PathExists(String),
+    /// Active when the command succeeds and prints a path that exists.
+    Query(String, Vec<String>),
+    /// Runs a probe, killed after `timeout` seconds, and checks how it exited and what it
+    /// printed.
+    Command {
+        exe: String,
+        args: Vec<String>,
+        timeout: u64,
+        /// `None` accepts any exit code.
+        exit: Option<i32>,
+        stdout: ValueMatch,
+    },
+    All(Vec<DetectMethod>),
+    Any(Vec<DetectMethod>),
+    Not(Box<DetectMethod>),
+}
+
+/// What the value of an environment variable, or the output of a probe, must look like.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum ValueMatch {
+    Any,
+    Exact(String),
+    Prefix(String),
+    /// Compiled when the rules are loaded, see `regex.rs` for the syntax.
+    Regex(Regex),
+}
+
+impl ValueMatch {
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            ValueMatch::Any => true,
+            ValueMatch::Exact(expected) => value == expected,
+            ValueMatch::Prefix(prefix) => value.starts_with(prefix.as_str()),
+            ValueMatch::Regex(regex) => regex.is_match(value),
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum CleanMethod {
-    RunCommand(ConstStr<64>, ConstStr<128>),
-    CleanPath(ConstStr<260>),
+    RunCommand(String, Vec<String>),
+    CleanPath(String),
+    /// Only removes entries unused for the given number of seconds.
+    CleanOlderThan(String, u64),
+    /// Evicts the least recently used files until the path fits in the given bytes.
+    CleanToBudget(String, u64),
+}
+
+/// What happens to the rest of a rule's steps when one fails.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OnError {
+    Stop,
+    Continue,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct CleanStep {
+    pub method: CleanMethod,
+    pub on_error: OnError,
+}
+
+impl CleanStep {
+    /// A step that stops the rule when it fails.
+    pub fn new(method: CleanMethod) -> Self {
+        Self {
+            method,
+            on_error: OnError::Stop,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct CacheRule {
-    pub name: ConstStr<32>,
+    pub name: String,
     pub detect: DetectMethod,
-    pub clean: CleanMethod,
-    pub size_dir: Option<ConstStr<260>>,
+    /// Run in order, at least one.
+    pub clean: Vec<CleanStep>,
+    pub size_dir: Option<SizeDir>,
 }
 
-fn get_exe_dir() -> ConstStr<260> {
-    let mut path = ConstStr::<260>::new();
+/// Where a rule's cache lives, measured for its size and cleared by `--unsafe`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum SizeDir {
+    Path(String),
+    /// Asks the tool, the first line it prints is the path.
+    Query(String, Vec<String>),
+}
+
+impl SizeDir {
+    /// Expands the path, or runs the query. A query must print an absolute path, tools
+    /// print things like `off` when the cache is disabled.
+    pub fn resolve(&self) -> Result<String, CleanError> {
+        match self {
+            SizeDir::Path(path) => expand_path(path),
+            SizeDir::Query(exe, args) => {
+                let path = query_command(exe, args)?;
+                if !Path::new(&path).is_absolute() {
+                    return Err(CleanError::Expand(format!(
+                        "`{} {}` printed `{}`, not an absolute path",
+                        exe,
+                        join_args(args),
+                        path
+                    )));
+                }
+
+                Ok(path)
+            }
+        }
+    }
+}
+
+fn get_exe_dir() -> String {
+    let mut path = String::new();
 
     if let Ok(exe_path) = env::current_exe() {
         if let Some(parent) = exe_path.parent() {
@@ -40,121 +144,156 @@ fn get_exe_dir() -> ConstStr<260> {
         }
     }
 
-    if path.len == 0 {
-        path.push_str(".");
+    if path.is_empty() {
+        path.push('.');
     }
 
     path
 }
 
-fn read_rules_file(path: &ConstStr<260>) -> Result<Vec<u8>, std::io::Error> {
-    fs::read(path.as_str())
+/// The rules template for this OS, compiled in so a lone executable still has rules.
+#[cfg(target_os = "windows")]
+pub const BUILTIN_RULES: &str = include_str!("../templates/windows/rules.txt");
+#[cfg(target_os = "macos")]
+pub const BUILTIN_RULES: &str = include_str!("../templates/macos/rules.txt");
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub const BUILTIN_RULES: &str = include_str!("../templates/linux/rules.txt");
+
+/// Stands in for a file name when the base layer is `BUILTIN_RULES`.
+pub const BUILTIN_SOURCE: &str = "<built-in>";
+
+pub enum RulesCommands {
+    Dump,
 }
 
-pub fn load_rules(out_rules: &mut [CacheRule; MAX_RULES]) -> usize {
-    let exe_dir = get_exe_dir();
+/// Reads a source returned by `rule_sources`.
+pub fn read_source(source: &str) -> std::io::Result<Vec<u8>> {
+    if source == BUILTIN_SOURCE {
+        return Ok(BUILTIN_RULES.as_bytes().to_vec());
+    }
+
+    fs::read(source)
+}
 
-    let mut rules_path = PathBuf::from(exe_dir.as_str());
-    rules_path.push("rules.txt");
+/// `rules.txt` next to the executable, else the template for this OS in `templates/`.
+fn exe_dir_rules() -> Option<PathBuf> {
+    let exe_dir = PathBuf::from(get_exe_dir());
 
-    let rules_str = ConstStr::from(rules_path.to_str().unwrap_or("rules.txt"));
+    let beside = exe_dir.join("rules.txt");
+    if beside.is_file() {
+        return Some(beside);
+    }
 
-    let file_content = match read_rules_file(&rules_str) {
-        Ok(content) => content,
-        Err(_) => {
-            // Try templates folder
-            let os_str = match std::env::consts::OS {
-                "linux" => "linux",
-                "macos" => "macos",
-                "windows" => "windows",
-                _ => {
-                    print_styled("<red>Error: <gray>Unsupported OS.");
-                    std::process::exit(1);
-                }
-            };
+    let template = exe_dir
+        .join("templates")
+        .join(env::consts::OS)
+        .join("rules.txt");
+    template.is_file().then_some(template)
+}
 
-            let mut templates_path = PathBuf::from(exe_dir.as_str());
-            templates_path.push("templates");
+/// Every `rules.*` file directly inside `dir`, sorted by name.
+fn rules_in(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.file_stem().is_some_and(|stem| stem == "rules") && path.is_file())
+        .collect();
 
-            if !templates_path.exists() {
-                print_styled("<red>Error: <gray>templates folder not found.");
-                std::process::exit(1);
-            }
+    files.sort();
+    files
+}
 
-            templates_path.push(os_str);
-            templates_path.push("rules.txt");
-
-            match read_rules_file(&ConstStr::from(
-                templates_path.to_str().unwrap_or("rules.txt"),
-            )) {
-                Ok(content) => content,
-                Err(_) => {
-                    print_styled(
-                        "<red>Error: <gray>Could not read rules.txt from templates/" + os_str + "/",
-                    );
-                    std::process::exit(1);
-                }
-            }
-        }
+/// `$XDG_CONFIG_HOME`, `%APPDATA%` on Windows, else `~/.config`.
+fn config_dir() -> PathBuf {
+    let dir = if cfg!(windows) {
+        env::var("APPDATA")
+    } else {
+        env::var("XDG_CONFIG_HOME")
     };
 
-    let content = match std::str::from_utf8(&file_content) {
-        Ok(s) => s,
-        Err(_) => return 0,
-    };
+    match dir {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(expand_home("~/.config")),
+    }
+}
+
+/// Every rules file that applies, lowest precedence first: the executable's directory or
+/// the built-in rules, `/etc/nil`, the user's config directory, `$NIL_RULES` and then the
+/// `--rules` files.
+pub fn rule_sources(explicit: &[String]) -> Vec<String> {
+    let mut sources: Vec<PathBuf> =
+        vec![exe_dir_rules().unwrap_or_else(|| PathBuf::from(BUILTIN_SOURCE))];
+
+    if !cfg!(windows) {
+        sources.extend(rules_in(Path::new("/etc/nil")));
+    }
+    sources.extend(rules_in(&config_dir().join("nil")));
 
-    let mut count = 0;
-    let mut lines = content.lines().filter(|l| !l.trim().is_empty());
+    let mut sources: Vec<String> = sources
+        .iter()
+        .filter_map(|path| path.to_str())
+        .map(str::to_string)
+        .collect();
 
-    while let Some(name) = lines.next() {
-        if count >= MAX_RULES {
-            break;
+    // Named explicitly, so a missing file is an error later rather than skipped here.
+    if let Ok(path) = env::var("NIL_RULES") {
+        if !path.is_empty() {
+            sources.push(path);
         }
+    }
+    sources.extend(explicit.iter().cloned());
 
-        let det_line = lines.next().unwrap_or("");
-        let detect = match det_line.as_bytes().get(0) {
-            Some(b'1') => DetectMethod::Binary(ConstStr::from(&det_line[1..])),
-            Some(b'2') => DetectMethod::EnvVar(ConstStr::from(&det_line[1..])),
-            _ => DetectMethod::PathExists(ConstStr::from(&det_line[1..])),
-        };
+    sources
+}
 
-        let clean_line = lines.next().unwrap_or("");
-        let clean = match clean_line.as_bytes().get(0) {
-            Some(b'1') => {
-                let cmd_part = &clean_line[1..];
-                if let Some(idx) = cmd_part.find(';') {
-                    CleanMethod::RunCommand(
-                        ConstStr::from(&cmd_part[..idx]),
-                        ConstStr::from(&cmd_part[idx + 1..]),
-                    )
-                } else {
-                    CleanMethod::RunCommand(ConstStr::from(cmd_part), ConstStr::new())
-                }
+fn print_error(message: &str) {
+    let mut msg = ConstStr::<512>::from("<red>Error: <gray>");
+    push_escaped(&mut msg, message);
+    eprint_styled(msg);
+}
+
+/// Loads every layer from `rule_sources`. A later file adds rules, replaces the ones with
+/// the same name, or turns them off with `enabled = false`.
+pub fn load_rules(explicit: &[String]) -> Vec<CacheRule> {
+    let sources = rule_sources(explicit);
+    let mut rules: Vec<CacheRule> = Vec::new();
+
+    for source in &sources {
+        let content = match read_source(source) {
+            Ok(content) => content,
+            Err(err) => {
+                print_error(&format!("Could not read {}: {}", source, err));
+                std::process::exit(1);
             }
-            _ => CleanMethod::CleanPath(ConstStr::from(&clean_line[1..])),
         };
 
-        let size_dir = lines
-            .next()
-            .map(|s| {
-                if s.is_empty() {
-                    None
-                } else {
-                    Some(ConstStr::from(s))
-                }
-            })
-            .flatten();
+        let file = parse_rules(source, &content);
+        if !file.errors.is_empty() {
+            for err in &file.errors {
+                print_error(&err.to_string());
+            }
+            std::process::exit(1);
+        }
 
-        out_rules[count] = CacheRule {
-            name: ConstStr::from(name),
-            detect,
-            clean,
-            size_dir,
-        };
-        count += 1;
+        for name in &file.disabled {
+            rules.retain(|r| !r.name.eq_ignore_ascii_case(name));
+        }
+
+        for parsed in file.rules {
+            let rule = parsed.rule;
+            match rules
+                .iter_mut()
+                .find(|r| r.name.eq_ignore_ascii_case(&rule.name))
+            {
+                Some(existing) => *existing = rule,
+                None => rules.push(rule),
+            }
+        }
     }
 
-    count
+    rules
 }
 
 pub fn path_exists(path: &str) -> bool {
@@ -162,12 +301,38 @@ pub fn path_exists(path: &str) -> bool {
 }
 
 pub fn is_rule_active(rule: &CacheRule) -> bool {
-    match &rule.detect {
-        DetectMethod::Binary(name) => find_executable(name.as_str()).is_some(),
-        DetectMethod::EnvVar(key) => env::var(key.as_str()).is_ok(),
-        DetectMethod::PathExists(path) => {
-            let expanded = expand_path(*path);
-            path_exists(expanded.as_str())
+    is_detected(&rule.detect)
+}
+
+fn is_detected(detect: &DetectMethod) -> bool {
+    match detect {
+        DetectMethod::Binary(name) => find_executable(name).is_some(),
+        DetectMethod::EnvVar {
+            name,
+            value,
+            empty_is_unset,
+        } => match env::var(name) {
+            Ok(found) if found.is_empty() && *empty_is_unset => false,
+            Ok(found) => value.matches(&found),
+            Err(_) => false,
+        },
+        DetectMethod::PathExists(path) => expand_path(path)
+            .is_ok_and(|path| expand_glob(&path).iter().any(|path| path_exists(path))),
+        DetectMethod::Query(exe, args) => query_command(exe, args)
+            .is_ok_and(|path| Path::new(&path).is_absolute() && path_exists(&path)),
+        DetectMethod::Command {
+            exe,
+            args,
+            timeout,
+            exit,
+            stdout,
+        } => {
+            probe_command(exe, args, Duration::from_secs(*timeout)).is_some_and(|(code, output)| {
+                exit.is_none_or(|exit| exit == code) && stdout.matches(output.trim())
+            })
         }
+        DetectMethod::All(conditions) => conditions.iter().all(is_detected),
+        DetectMethod::Any(conditions) => conditions.iter().any(is_detected),
+        DetectMethod::Not(condition) => !is_detected(condition),
     }
 }
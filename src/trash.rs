@@ -0,0 +1,394 @@
+use crate::{
+    cli::Options,
+    error::CleanError,
+    files::{expand_home, folder_size, human_duration, human_size},
+    minimessage_const::ConstStr,
+    report::{Record, Report},
+    ui::push_escaped,
+};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub enum TrashCommands {
+    List,
+    Purge,
+}
+
+/// One `clean --trash` of one rule: the moved entries plus where they came from.
+pub struct Batch {
+    pub dir: PathBuf,
+    pub rule: String,
+    pub source: String,
+    /// Unix time of the clean, in seconds.
+    pub time: u64,
+}
+
+impl Batch {
+    pub fn files(&self) -> PathBuf {
+        self.dir.join("files")
+    }
+}
+
+/// `$NIL_TRASH`, or a `nil/trash` folder in the user's data directory.
+pub fn trash_root() -> PathBuf {
+    if let Ok(dir) = env::var("NIL_TRASH") {
+        return PathBuf::from(dir);
+    }
+
+    let data = if cfg!(windows) {
+        env::var("LOCALAPPDATA").ok().map(PathBuf::from)
+    } else {
+        env::var("XDG_DATA_HOME").ok().map(PathBuf::from)
+    };
+
+    let mut root = data.unwrap_or_else(|| {
+        let fallback = if cfg!(windows) {
+            "~/AppData/Local"
+        } else {
+            "~/.local/share"
+        };
+        PathBuf::from(expand_home(fallback))
+    });
+    root.push("nil");
+    root.push("trash");
+    root
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Moves everything inside `source` into a new trash batch for `rule`. Entries that
+/// can't be moved stay where they are and are returned as errors. A missing or empty
+/// `source` makes no batch, there is nothing to restore.
+pub fn move_to_trash(rule: &str, source: &str) -> Result<Option<Batch>, Vec<CleanError>> {
+    let entries: Vec<fs::DirEntry> = match fs::read_dir(source) {
+        Ok(entries) => entries.flatten().collect(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(vec![CleanError::from_io(source, &err)]),
+    };
+    if entries.is_empty() {
+        return Ok(None);
+    }
+
+    let time = now();
+
+    // Rule names are free text, keep only what is safe in a file name.
+    let safe_name: String = rule
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    let root = trash_root();
+    let root_str = root.to_str().unwrap_or("?");
+    if let Err(err) = fs::create_dir_all(&root) {
+        return Err(vec![CleanError::from_io(root_str, &err)]);
+    }
+
+    // Claimed with `create_dir`, which fails if it exists, so parallel rules whose names
+    // sanitize the same never share a batch.
+    let mut dir = root.join(format!("{}-{}", time, safe_name));
+    let mut suffix = 1;
+    loop {
+        match fs::create_dir(&dir) {
+            Ok(()) => break,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                suffix += 1;
+                dir = root.join(format!("{}-{}-{}", time, safe_name, suffix));
+            }
+            Err(err) => return Err(vec![CleanError::from_io(root_str, &err)]),
+        }
+    }
+
+    let batch = Batch {
+        dir,
+        rule: rule.to_string(),
+        source: source.to_string(),
+        time,
+    };
+
+    let dir_str = batch.dir.to_str().unwrap_or("?");
+    if let Err(err) = fs::create_dir(batch.files()) {
+        return Err(vec![CleanError::from_io(dir_str, &err)]);
+    }
+
+    // The manifest goes first so a half finished move can still be restored.
+    let manifest = format!(
+        "rule={}\nsource={}\ntime={}\n",
+        batch.rule, batch.source, batch.time
+    );
+    if let Err(err) = fs::write(batch.dir.join("manifest"), manifest) {
+        return Err(vec![CleanError::from_io(dir_str, &err)]);
+    }
+
+    let mut errors = Vec::new();
+    for entry in entries {
+        let from = entry.path();
+        if let Err(err) = move_entry(&from, &batch.files().join(entry.file_name())) {
+            errors.push(CleanError::from_io(from.to_str().unwrap_or("?"), &err));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Some(batch))
+    } else {
+        Err(errors)
+    }
+}
+
+/// Renames `from` to `to`, copying and deleting when they are on different filesystems.
+fn move_entry(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    copy_recursive(from, to)?;
+    if fs::symlink_metadata(from)?.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    }
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(from)?.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+/// Every batch in the trash, oldest first.
+pub fn list_batches() -> Vec<Batch> {
+    let mut batches = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(trash_root()) {
+        for entry in entries.flatten() {
+            let Ok(manifest) = fs::read_to_string(entry.path().join("manifest")) else {
+                continue;
+            };
+
+            let mut batch = Batch {
+                dir: entry.path(),
+                rule: String::new(),
+                source: String::new(),
+                time: 0,
+            };
+
+            for line in manifest.lines() {
+                match line.split_once('=') {
+                    Some(("rule", value)) => batch.rule = value.to_string(),
+                    Some(("source", value)) => batch.source = value.to_string(),
+                    Some(("time", value)) => batch.time = value.parse().unwrap_or(0),
+                    _ => {}
+                }
+            }
+
+            if !batch.source.is_empty() {
+                batches.push(batch);
+            }
+        }
+    }
+
+    batches.sort_by_key(|b| b.time);
+    batches
+}
+
+/// Moves a batch back to where it came from. Entries that exist again at the source are
+/// left in the trash and returned as conflicts; the batch is only removed once empty.
+fn restore_batch(batch: &Batch) -> (u64, Vec<String>, Vec<CleanError>) {
+    let mut restored = 0;
+    let mut conflicts = Vec::new();
+    let mut errors = Vec::new();
+
+    if let Err(err) = fs::create_dir_all(&batch.source) {
+        errors.push(CleanError::from_io(&batch.source, &err));
+        return (restored, conflicts, errors);
+    }
+
+    if let Ok(entries) = fs::read_dir(batch.files()) {
+        for entry in entries.flatten() {
+            let to = Path::new(&batch.source).join(entry.file_name());
+            if to.exists() {
+                conflicts.push(to.to_str().unwrap_or("?").to_string());
+                continue;
+            }
+
+            match move_entry(&entry.path(), &to) {
+                Ok(()) => restored += 1,
+                Err(err) => errors.push(CleanError::from_io(to.to_str().unwrap_or("?"), &err)),
+            }
+        }
+    }
+
+    if conflicts.is_empty() && errors.is_empty() {
+        let _ = fs::remove_dir_all(&batch.dir);
+    }
+
+    (restored, conflicts, errors)
+}
+
+/// `nil restore [RULE]...`, newest batches first so the latest clean wins.
+pub fn run_restore(options: &Options, report: &mut Report) {
+    let mut msg = ConstStr::<512>::new();
+    let mut failed = false;
+    let mut found = false;
+
+    for batch in list_batches().iter().rev() {
+        if !options.selects(&batch.rule) {
+            continue;
+        }
+        found = true;
+
+        let (restored, conflicts, errors) = restore_batch(batch);
+
+        msg.clear();
+        msg.push_str("<green>Restored <white>");
+        msg.push_u64(restored);
+        msg.push_str("</white> entries of <yellow>");
+        push_escaped(&mut msg, &batch.rule);
+        msg.push_str("</yellow> to <gray>\"");
+        push_escaped(&mut msg, &batch.source);
+        msg.push_u8(b'"');
+        report.text(msg);
+
+        for conflict in &conflicts {
+            msg.clear();
+            msg.push_str("<yellow>Kept in trash, already exists: <gray>");
+            push_escaped(&mut msg, conflict);
+            report.text(msg);
+        }
+        for err in &errors {
+            msg.clear();
+            msg.push_str("<red>Failed: <white>");
+            push_escaped(&mut msg, &err.to_string());
+            report.text(msg);
+        }
+
+        failed |= !conflicts.is_empty() || !errors.is_empty();
+
+        report.record(
+            Record::new("restore")
+                .str("name", &batch.rule)
+                .str("path", &batch.source)
+                .u64("time", batch.time)
+                .u64("restored", restored)
+                .u64("conflicts", conflicts.len() as u64)
+                .u64("errors", errors.len() as u64),
+        );
+    }
+
+    if !found {
+        report.text("<gray>Nothing to restore.");
+    }
+    report.finish();
+
+    if failed {
+        exit(1);
+    }
+}
+
+/// `nil trash list` and `nil trash purge [--older-than AGE]`.
+pub fn run_trash(command: TrashCommands, options: &Options, report: &mut Report) {
+    let mut msg = ConstStr::<512>::new();
+    let now = now();
+    let batches = list_batches();
+
+    match command {
+        TrashCommands::List => {
+            msg.clear();
+            msg.push_str("<white>Trash <gray>(");
+            push_escaped(&mut msg, trash_root().to_str().unwrap_or("?"));
+            msg.push_str("):");
+            report.text(msg);
+
+            for batch in &batches {
+                let size = folder_size(batch.files().to_str().unwrap_or(""));
+
+                msg.clear();
+                msg.push_str("  <yellow>");
+                push_escaped(&mut msg, &batch.rule);
+                msg.push_str(" <white>");
+                msg.push_str(&human_size(size));
+                msg.push_str(" <gray>");
+                msg.push_str(&human_duration(now.saturating_sub(batch.time)));
+                msg.push_str(" ago, from \"");
+                push_escaped(&mut msg, &batch.source);
+                msg.push_u8(b'"');
+                report.text(msg);
+
+                report.record(
+                    Record::new("batch")
+                        .str("name", &batch.rule)
+                        .str("path", &batch.source)
+                        .u64("time", batch.time)
+                        .u64("size_bytes", size),
+                );
+            }
+
+            if batches.is_empty() {
+                report.text("  <gray>Empty.");
+            }
+        }
+        TrashCommands::Purge => {
+            let min_age = options.older_than.map_or(0, |age| age.as_secs());
+            let mut purged: u64 = 0;
+            let mut freed: u64 = 0;
+            let mut failed = false;
+
+            for batch in &batches {
+                if now.saturating_sub(batch.time) < min_age || !options.selects(&batch.rule) {
+                    continue;
+                }
+
+                let size = folder_size(batch.dir.to_str().unwrap_or(""));
+                if let Err(err) = fs::remove_dir_all(&batch.dir) {
+                    let err = CleanError::from_io(batch.dir.to_str().unwrap_or("?"), &err);
+                    msg.clear();
+                    msg.push_str("<red>Failed: <white>");
+                    push_escaped(&mut msg, &err.to_string());
+                    report.text(msg);
+
+                    failed = true;
+                    continue;
+                }
+
+                purged += 1;
+                freed += size;
+
+                report.record(
+                    Record::new("purge")
+                        .str("name", &batch.rule)
+                        .u64("time", batch.time)
+                        .u64("freed_bytes", size),
+                );
+            }
+
+            msg.clear();
+            msg.push_str("<green>Purged ");
+            msg.push_u64(purged);
+            msg.push_str(" batches <dark_green>(");
+            msg.push_str(&human_size(freed));
+            msg.push_str(" freed)</dark_green>.");
+            report.text(msg);
+
+            if failed {
+                report.finish();
+                exit(1);
+            }
+        }
+    }
+
+    report.finish();
+}
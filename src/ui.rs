@@ -1,4 +1,4 @@
-use crate::minimessage_const::serialize;
+use crate::minimessage_const::{serialize, ConstStr};
 use std::io::Write;
 
 pub fn println(message: impl AsRef<str>) {
@@ -13,3 +13,46 @@ pub fn print_styled(message: impl AsRef<str>) {
     let serialized = serialize::<512>(message.as_ref());
     println(serialized.as_str());
 }
+
+/// Like `print_styled`, but to stderr so errors stay out of machine-readable output.
+pub fn eprint_styled(message: impl AsRef<str>) {
+    let serialized = serialize::<512>(message.as_ref());
+
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(serialized.as_bytes());
+    let _ = stderr.write_all(b"\n");
+}
+
+/// Appends `text` to `out` so that `serialize` prints it verbatim. Stops at the first
+/// character that does not fit, rather than splitting it.
+pub fn push_escaped<const N: usize>(out: &mut ConstStr<N>, text: &str) {
+    let mut buffer = [0u8; 4];
+
+    for c in text.chars() {
+        let fits = match c {
+            '\\' => out.try_push_str("\\\\"),
+            '<' => out.try_push_str("\\<"),
+            c => out.try_push_str(c.encode_utf8(&mut buffer)),
+        };
+        if fits.is_err() {
+            break;
+        }
+    }
+}
+
+/// Prints `message` to stderr without a newline and reads one answer from stdin.
+/// Returns the lowercased first character, or `None` on an empty answer or EOF.
+pub fn ask(message: impl AsRef<str>) -> Option<char> {
+    let serialized = serialize::<512>(message.as_ref());
+
+    // stderr keeps the prompt out of machine-readable output on stdout.
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(serialized.as_bytes());
+    let _ = stderr.flush();
+
+    let mut answer = String::new();
+    match std::io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => answer.trim().chars().next().map(|c| c.to_ascii_lowercase()),
+    }
+}